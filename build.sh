@@ -4,3 +4,9 @@ cargo build --release --target wasm32-unknown-unknown
 # Build ESM modules in coco-ui
 wasm-bindgen target/wasm32-unknown-unknown/release/coco_ui.wasm --out-dir coco-ui/vendor --target web --no-typescript
 
+# Assemble the example ROMs, along with the device definitions they include
+cargo run --release -p coco-asm -- --header coco-ui/roms/devices.tal
+for tal in coco-ui/roms/*.tal; do
+  [ "$tal" = coco-ui/roms/devices.tal ] && continue
+  cargo run --release -p coco-asm -- "$tal" "${tal%.tal}.rom"
+done
@@ -0,0 +1,618 @@
+use std::collections::HashMap;
+use std::fs;
+use std::iter;
+use std::path::{Path, PathBuf};
+
+use coco_core::opcodes::{self, from_mnemonic};
+
+use crate::lexer::Lexer;
+use crate::{Error, ErrorKind, Location, Program, Symbol};
+
+/// Address ROMs are loaded at
+pub const ROM_START: usize = 0x100;
+const MEMORY_LEN: usize = 0x10000;
+
+/// How a label reference gets written once the label address is known.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RefKind {
+    /// Low byte of a zero page address (`.label`)
+    ZeroPage,
+    /// Signed byte offset from the end of the literal (`,label`)
+    Relative,
+    /// Absolute short address (`;label`)
+    Absolute,
+    /// Short offset from the end of an immediate jump (`!label`, `?label`)
+    RelativeShort,
+}
+
+#[derive(Debug, Clone)]
+struct Reference {
+    label: String,
+    /// Address of the first byte to patch
+    addr: usize,
+    kind: RefKind,
+    location: Location,
+}
+
+#[derive(Debug)]
+pub struct Assembler {
+    memory: Box<[u8; MEMORY_LEN]>,
+    /// Address the next byte will be written to
+    ptr: usize,
+    /// One past the highest address written to
+    len: usize,
+    /// Name of the last `@label`, which `&sublabels` belong to
+    scope: String,
+    /// Location of the token being assembled
+    location: Location,
+    labels: HashMap<String, u16>,
+    symbols: Vec<Symbol>,
+    macros: HashMap<String, Vec<(String, Location)>>,
+    references: Vec<Reference>,
+    /// Macros being expanded, to catch recursion
+    expanding: Vec<String>,
+    /// Directories to search for included files
+    include_paths: Vec<PathBuf>,
+    /// Files being assembled, from the outermost one to the current one
+    files: Vec<PathBuf>,
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Self {
+            memory: Box::new([0x00; MEMORY_LEN]),
+            ptr: 0,
+            len: 0,
+            scope: String::new(),
+            location: Location::default(),
+            labels: HashMap::new(),
+            symbols: Vec::new(),
+            macros: HashMap::new(),
+            references: Vec::new(),
+            expanding: Vec::new(),
+            include_paths: Vec::new(),
+            files: Vec::new(),
+        }
+    }
+
+    /// Adds a directory to search for included files. Included files are
+    /// looked up next to the file including them first, then in these
+    /// directories, in the order they were added.
+    pub fn include_path(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.include_paths.push(dir.into());
+        self
+    }
+
+    /// Assembles a Tal source. `file` is the path errors are reported with,
+    /// and the one `~includes` are relative to.
+    pub fn assemble(mut self, file: &str, source: &str) -> Result<Program, Error> {
+        self.source(file, source)?;
+        self.resolve()?;
+
+        Ok(Program {
+            rom: self.memory[ROM_START..self.len.max(ROM_START)].to_vec(),
+            symbols: self.symbols,
+        })
+    }
+
+    fn source(&mut self, file: &str, source: &str) -> Result<(), Error> {
+        self.files.push(canonical(Path::new(file)));
+
+        let mut tokens = Lexer::new(file, source);
+        while let Some(token) = tokens.next() {
+            let token = token?;
+            match token.text.strip_prefix('%') {
+                Some(name) => {
+                    self.location = token.location;
+                    self.define_macro(name, &mut tokens)?
+                }
+                None => self.token(token.text, token.location)?,
+            }
+        }
+
+        self.files.pop();
+        Ok(())
+    }
+
+    fn include(&mut self, name: &str) -> Result<(), Error> {
+        let dir = self
+            .files
+            .last()
+            .and_then(|file| file.parent())
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        let Some(path) = iter::once(&dir)
+            .chain(&self.include_paths)
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+        else {
+            return Err(self.error(ErrorKind::IncludeNotFound(name.to_string())));
+        };
+
+        if self.files.contains(&canonical(&path)) {
+            return Err(self.error(ErrorKind::IncludeCycle(name.to_string())));
+        }
+        let source = fs::read_to_string(&path).map_err(|err| {
+            self.error(ErrorKind::UnreadableInclude {
+                path: path.display().to_string(),
+                reason: err.to_string(),
+            })
+        })?;
+
+        self.source(&path.display().to_string(), &source)
+    }
+
+    fn error(&self, kind: ErrorKind) -> Error {
+        Error::new(kind, &self.location)
+    }
+
+    fn define_macro(&mut self, name: &str, tokens: &mut Lexer) -> Result<(), Error> {
+        self.check_name(name)?;
+        if self.macros.contains_key(name) {
+            return Err(self.error(ErrorKind::DuplicateMacro(name.to_string())));
+        }
+        if tokens.next().transpose()?.map(|token| token.text) != Some("{") {
+            return Err(self.error(ErrorKind::MissingMacroBody(name.to_string())));
+        }
+
+        let mut body = vec![];
+        loop {
+            match tokens.next().transpose()? {
+                Some(token) if token.text == "}" => break,
+                Some(token) => body.push((token.text.to_string(), token.location)),
+                None => return Err(self.error(ErrorKind::UnterminatedMacro(name.to_string()))),
+            }
+        }
+
+        self.macros.insert(name.to_string(), body);
+        Ok(())
+    }
+
+    fn token(&mut self, token: &str, location: Location) -> Result<(), Error> {
+        self.location = location;
+        let (rune, rest) = token.split_at(token.chars().next().map_or(0, char::len_utf8));
+
+        match rune {
+            "|" => self.ptr = self.parse_hex(rest)? as usize,
+            "$" => self.ptr += self.parse_hex(rest)? as usize,
+            "@" => {
+                self.define_label(rest.to_string())?;
+                self.scope = rest.to_string();
+            }
+            "&" => self.define_label(format!("{}/{}", self.scope, rest))?,
+            "#" => match (rest.len(), self.parse_hex(rest)) {
+                (2, Ok(value)) => self.write_bytes(&[opcodes::PUSH, value as u8])?,
+                (4, Ok(value)) => {
+                    self.write_byte(opcodes::PUSH2)?;
+                    self.write_short(value)?;
+                }
+                _ => return Err(self.error(ErrorKind::InvalidHex(token.to_string()))),
+            },
+            "." => {
+                self.write_byte(opcodes::PUSH)?;
+                self.reference(rest, RefKind::ZeroPage)?;
+            }
+            "," => {
+                self.write_byte(opcodes::PUSH)?;
+                self.reference(rest, RefKind::Relative)?;
+            }
+            ";" => {
+                self.write_byte(opcodes::PUSH2)?;
+                self.reference(rest, RefKind::Absolute)?;
+            }
+            "!" => {
+                self.write_byte(opcodes::JMI)?;
+                self.reference(rest, RefKind::RelativeShort)?;
+            }
+            "?" => {
+                self.write_byte(opcodes::JCI)?;
+                self.reference(rest, RefKind::RelativeShort)?;
+            }
+            "\"" => self.write_bytes(rest.as_bytes())?,
+            "~" => self.include(rest)?,
+            "[" | "]" if rest.is_empty() => {}
+            _ => self.word(token)?,
+        }
+
+        Ok(())
+    }
+
+    /// Handles a token without a rune: raw hex, an opcode, a macro or a
+    /// call to a label
+    fn word(&mut self, token: &str) -> Result<(), Error> {
+        if let Some(bytes) = parse_raw_hex(token) {
+            return self.write_bytes(&bytes);
+        }
+        if let Some(opcode) = from_mnemonic(token) {
+            return self.write_byte(opcode);
+        }
+        if let Some(body) = self.macros.get(token).cloned() {
+            if self.expanding.iter().any(|name| name == token) {
+                return Err(self.error(ErrorKind::MacroRecursion(token.to_string())));
+            }
+            self.expanding.push(token.to_string());
+            for (token, location) in body {
+                self.token(&token, location)?;
+            }
+            self.expanding.pop();
+            return Ok(());
+        }
+
+        self.write_byte(opcodes::JSI)?;
+        self.reference(token, RefKind::RelativeShort)
+    }
+
+    fn define_label(&mut self, name: String) -> Result<(), Error> {
+        self.check_name(&name)?;
+        if self.labels.contains_key(&name) {
+            return Err(self.error(ErrorKind::DuplicateLabel(name)));
+        }
+
+        // padding can move past the end of memory, where no label can point
+        if self.ptr >= MEMORY_LEN {
+            return Err(self.error(ErrorKind::OutOfMemory));
+        }
+        let addr = self.ptr as u16;
+        self.labels.insert(name.clone(), addr);
+        self.symbols.push(Symbol { addr, name });
+        Ok(())
+    }
+
+    fn check_name(&self, name: &str) -> Result<(), Error> {
+        if name.is_empty() || parse_raw_hex(name).is_some() || from_mnemonic(name).is_some() {
+            return Err(self.error(ErrorKind::InvalidName(name.to_string())));
+        }
+        Ok(())
+    }
+
+    /// Records a reference to a label and leaves room for its value
+    fn reference(&mut self, label: &str, kind: RefKind) -> Result<(), Error> {
+        let label = match label.strip_prefix('&') {
+            Some(sublabel) => format!("{}/{}", self.scope, sublabel),
+            None => label.to_string(),
+        };
+        self.references.push(Reference {
+            label,
+            addr: self.ptr,
+            kind,
+            location: self.location.clone(),
+        });
+
+        match kind {
+            RefKind::ZeroPage | RefKind::Relative => self.write_byte(0xff),
+            RefKind::Absolute | RefKind::RelativeShort => self.write_short(0xffff),
+        }
+    }
+
+    fn resolve(&mut self) -> Result<(), Error> {
+        for reference in &self.references {
+            let error = |kind| Err(Error::new(kind, &reference.location));
+            let Some(&target) = self.labels.get(&reference.label) else {
+                return error(ErrorKind::UndefinedLabel(reference.label.clone()));
+            };
+            let addr = reference.addr;
+
+            match reference.kind {
+                RefKind::ZeroPage => {
+                    if target > 0xff {
+                        return error(ErrorKind::NotInZeroPage {
+                            label: reference.label.clone(),
+                            addr: target,
+                        });
+                    }
+                    self.memory[addr] = target as u8;
+                }
+                RefKind::Relative => {
+                    let offset = target as i32 - (addr as i32 + 2);
+                    if !(i8::MIN as i32..=i8::MAX as i32).contains(&offset) {
+                        return error(ErrorKind::RelativeJumpTooFar {
+                            label: reference.label.clone(),
+                            offset,
+                        });
+                    }
+                    self.memory[addr] = offset as u8;
+                }
+                RefKind::Absolute => {
+                    self.memory[addr..addr + 2].copy_from_slice(&target.to_be_bytes());
+                }
+                RefKind::RelativeShort => {
+                    let offset = target.wrapping_sub((addr as u16).wrapping_add(2));
+                    self.memory[addr..addr + 2].copy_from_slice(&offset.to_be_bytes());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_byte(&mut self, byte: u8) -> Result<(), Error> {
+        if self.ptr >= MEMORY_LEN {
+            return Err(self.error(ErrorKind::OutOfMemory));
+        }
+        if self.ptr < ROM_START {
+            return Err(self.error(ErrorKind::ZeroPageWrite(self.ptr as u16)));
+        }
+
+        self.memory[self.ptr] = byte;
+        self.ptr += 1;
+        self.len = self.len.max(self.ptr);
+        Ok(())
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        bytes.iter().try_for_each(|&byte| self.write_byte(byte))
+    }
+
+    fn write_short(&mut self, short: u16) -> Result<(), Error> {
+        self.write_bytes(&short.to_be_bytes())
+    }
+
+    /// Parses a hex number of up to 4 lowercase digits
+    fn parse_hex(&self, s: &str) -> Result<u16, Error> {
+        let is_valid = (1..=4).contains(&s.len()) && s.bytes().all(is_hex_digit);
+        match is_valid {
+            true => u16::from_str_radix(s, 16)
+                .map_err(|_| self.error(ErrorKind::InvalidHex(s.to_string()))),
+            false => Err(self.error(ErrorKind::InvalidHex(s.to_string()))),
+        }
+    }
+}
+
+impl Default for Assembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns the canonical form of a path, or the path itself for files that
+/// don't exist (e.g. sources that don't come from disk)
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Parses a raw byte (2 digits) or short (4 digits) into its bytes
+fn parse_raw_hex(s: &str) -> Option<Vec<u8>> {
+    if !matches!(s.len(), 2 | 4) || !s.bytes().all(is_hex_digit) {
+        return None;
+    }
+    let value = u16::from_str_radix(s, 16).ok()?;
+
+    match s.len() {
+        2 => Some(vec![value as u8]),
+        _ => Some(value.to_be_bytes().to_vec()),
+    }
+}
+
+fn is_hex_digit(c: u8) -> bool {
+    c.is_ascii_digit() || (b'a'..=b'f').contains(&c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use coco_core::opcodes::*;
+
+    fn assemble(source: &str) -> Result<Vec<u8>, ErrorKind> {
+        Assembler::new()
+            .assemble("test.tal", source)
+            .map(|program| program.rom)
+            .map_err(|err| err.kind)
+    }
+
+    /// Returns the `line:column` where assembling the source fails
+    fn error_at(source: &str) -> String {
+        let err = Assembler::new().assemble("test.tal", source).unwrap_err();
+        format!("{}:{}", err.location.line, err.location.column)
+    }
+
+    #[test]
+    fn assembles_opcodes_with_modes() {
+        assert_eq!(
+            assemble("|0100 INC ADD2 POPk STH2r JMP2kr JCN LIT BRK"),
+            Ok(vec![
+                INC,
+                ADD2,
+                POP | FLAG_KEEP,
+                STH2 | FLAG_RET,
+                JMP2 | FLAG_KEEP | FLAG_RET,
+                JNZ,
+                PUSH,
+                BRK
+            ])
+        );
+    }
+
+    #[test]
+    fn assembles_literals_and_raw_bytes() {
+        assert_eq!(
+            assemble("|0100 #12 #abcd 0077 7700 ff \"hi"),
+            Ok(vec![
+                PUSH, 0x12, PUSH2, 0xab, 0xcd, 0x00, 0x77, 0x77, 0x00, 0xff, b'h', b'i'
+            ])
+        );
+        assert_eq!(
+            assemble("|0100 #123"),
+            Err(ErrorKind::InvalidHex("#123".to_string()))
+        );
+    }
+
+    #[test]
+    fn pads_memory() {
+        assert_eq!(
+            assemble("|0100 #01 $2 #02 |0108 BRK"),
+            Ok(vec![PUSH, 0x01, 0x00, 0x00, PUSH, 0x02, 0x00, 0x00, BRK])
+        );
+    }
+
+    #[test]
+    fn resolves_label_references() {
+        let source = "
+            |10 @Screen &vector $2 &x $1
+            |0000 @x $1
+            |0100
+            @main
+                .Screen/x .x ;data
+            @data
+                01
+        ";
+
+        assert_eq!(
+            assemble(source),
+            Ok(vec![PUSH, 0x12, PUSH, 0x00, PUSH2, 0x01, 0x07, 0x01])
+        );
+    }
+
+    #[test]
+    fn resolves_relative_references() {
+        let source = "
+            |0100
+            @loop
+                ,&end JCN ,loop JMP
+                &end BRK
+        ";
+
+        assert_eq!(
+            assemble(source),
+            Ok(vec![PUSH, 0x03, JNZ, PUSH, 0xfa, JMP, BRK])
+        );
+    }
+
+    #[test]
+    fn resolves_immediate_jumps() {
+        let source = "|0100 @main !main ?end routine @end BRK @routine JMP2r";
+
+        assert_eq!(
+            assemble(source),
+            Ok(vec![
+                JMI,
+                0xff,
+                0xfd,
+                JCI,
+                0x00,
+                0x03,
+                JSI,
+                0x00,
+                0x01,
+                BRK,
+                JMP2 | FLAG_RET
+            ])
+        );
+    }
+
+    #[test]
+    fn checks_relative_jump_range() {
+        let source = "|0100 ,far JMP $80 @far BRK";
+
+        assert_eq!(
+            assemble(source),
+            Err(ErrorKind::RelativeJumpTooFar {
+                label: "far".to_string(),
+                offset: 128
+            })
+        );
+        assert!(assemble("|0100 ,far JMP $7f @far BRK").is_ok());
+    }
+
+    #[test]
+    fn expands_macros() {
+        let source = "
+            %MOD { DUP2 DIV MUL SUB }
+            %MOD2 { MOD MOD }
+            |0100 #07 #03 MOD2
+        ";
+
+        assert_eq!(
+            assemble(source),
+            Ok(vec![
+                PUSH, 0x07, PUSH, 0x03, DUP2, DIV, MUL, SUB, DUP2, DIV, MUL, SUB
+            ])
+        );
+    }
+
+    #[test]
+    fn reports_label_errors() {
+        assert_eq!(
+            assemble("|0100 ;nowhere"),
+            Err(ErrorKind::UndefinedLabel("nowhere".to_string()))
+        );
+        assert_eq!(
+            assemble("|0100 @main @main"),
+            Err(ErrorKind::DuplicateLabel("main".to_string()))
+        );
+        assert_eq!(
+            assemble("|0100 @ADD"),
+            Err(ErrorKind::InvalidName("ADD".to_string()))
+        );
+        assert_eq!(
+            assemble("|0100 .main @main"),
+            Err(ErrorKind::NotInZeroPage {
+                label: "main".to_string(),
+                addr: 0x102
+            })
+        );
+    }
+
+    #[test]
+    fn reports_macro_errors() {
+        assert_eq!(
+            assemble("%LOOP { INC LOOP } |0100 LOOP"),
+            Err(ErrorKind::MacroRecursion("LOOP".to_string()))
+        );
+        assert_eq!(
+            assemble("%INC2 { INC INC }"),
+            Err(ErrorKind::InvalidName("INC2".to_string()))
+        );
+        assert_eq!(
+            assemble("%FOO { INC"),
+            Err(ErrorKind::UnterminatedMacro("FOO".to_string()))
+        );
+    }
+
+    #[test]
+    fn reports_writes_outside_the_rom() {
+        assert_eq!(assemble("|ffff 01 02"), Err(ErrorKind::OutOfMemory));
+        assert_eq!(
+            assemble("|0100 BRK |00 @x 01"),
+            Err(ErrorKind::ZeroPageWrite(0x00))
+        );
+        assert_eq!(assemble("|0000 @x $1 |0100 BRK"), Ok(vec![BRK]));
+    }
+
+    #[test]
+    fn reports_labels_past_the_end_of_memory() {
+        assert_eq!(assemble("|ffff $2 @x"), Err(ErrorKind::OutOfMemory));
+        assert_eq!(assemble("|ffff $1 @x"), Err(ErrorKind::OutOfMemory));
+        assert!(assemble("|0100 BRK |ffff @x").is_ok());
+    }
+
+    #[test]
+    fn reports_error_locations() {
+        assert_eq!(error_at("|0100\n  ;nowhere BRK"), "2:3");
+        assert_eq!(error_at("|0100 @main\n@main"), "2:1");
+        assert_eq!(error_at("|0100\n,far JMP\n$80 @far"), "2:1");
+        assert_eq!(error_at("%LOOP {\n  INC LOOP\n}\n|0100 LOOP"), "2:7");
+        assert_eq!(error_at("|0100 BRK\n|00 #01"), "2:5");
+    }
+
+    #[test]
+    fn collects_symbols() {
+        let source = "|10 @Screen &x $1 |0100 @main BRK &loop";
+
+        let program = Assembler::new().assemble("test.tal", source).unwrap();
+
+        let symbols: Vec<_> = program
+            .symbols
+            .iter()
+            .map(|symbol| (symbol.addr, symbol.name.as_str()))
+            .collect();
+        assert_eq!(
+            symbols,
+            vec![
+                (0x10, "Screen"),
+                (0x10, "Screen/x"),
+                (0x100, "main"),
+                (0x101, "main/loop")
+            ]
+        );
+    }
+}
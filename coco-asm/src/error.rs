@@ -0,0 +1,115 @@
+use std::fmt;
+
+/// A position in a source file. Lines and columns start at 1.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Location {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+/// The reasons a Tal source can fail to assemble.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// A comment was still open at the end of the source
+    UnterminatedComment,
+    /// A macro body was still open at the end of the source
+    UnterminatedMacro(String),
+    /// A macro definition is not followed by `{`
+    MissingMacroBody(String),
+    /// A rune expected a hex number and got something else
+    InvalidHex(String),
+    /// A label or macro name clashes with an opcode or a hex number
+    InvalidName(String),
+    DuplicateLabel(String),
+    DuplicateMacro(String),
+    UndefinedLabel(String),
+    /// A macro (directly or indirectly) expands into itself
+    MacroRecursion(String),
+    /// A `,` reference points further than a signed byte can reach
+    RelativeJumpTooFar {
+        label: String,
+        offset: i32,
+    },
+    /// A `.` reference points to a label outside of the zero page
+    NotInZeroPage {
+        label: String,
+        addr: u16,
+    },
+    /// Bytes were written below `0x100`, where they'd be left out of the ROM
+    ZeroPageWrite(u16),
+    /// Bytes were written past the end of memory
+    OutOfMemory,
+    /// No file to include was found with this name
+    IncludeNotFound(String),
+    /// A file (directly or indirectly) includes itself
+    IncludeCycle(String),
+    /// A file to include was found, but could not be read
+    UnreadableInclude {
+        path: String,
+        reason: String,
+    },
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnterminatedComment => write!(f, "unterminated comment"),
+            Self::UnterminatedMacro(name) => write!(f, "unterminated macro `{}`", name),
+            Self::MissingMacroBody(name) => write!(f, "macro `{}` has no body", name),
+            Self::InvalidHex(token) => write!(f, "invalid hex number `{}`", token),
+            Self::InvalidName(name) => write!(f, "invalid name `{}`", name),
+            Self::DuplicateLabel(name) => write!(f, "duplicate label `{}`", name),
+            Self::DuplicateMacro(name) => write!(f, "duplicate macro `{}`", name),
+            Self::UndefinedLabel(name) => write!(f, "undefined label `{}`", name),
+            Self::MacroRecursion(name) => write!(f, "macro `{}` expands into itself", name),
+            Self::RelativeJumpTooFar { label, offset } => write!(
+                f,
+                "relative reference to `{}` is out of range ({} bytes away)",
+                label, offset
+            ),
+            Self::NotInZeroPage { label, addr } => write!(
+                f,
+                "label `{}` at {:04x} is not in the zero page",
+                label, addr
+            ),
+            Self::ZeroPageWrite(addr) => write!(f, "writing into the zero page at {:04x}", addr),
+            Self::OutOfMemory => write!(f, "program does not fit in memory"),
+            Self::IncludeNotFound(name) => write!(f, "could not find `{}` to include", name),
+            Self::IncludeCycle(name) => write!(f, "`{}` includes itself", name),
+            Self::UnreadableInclude { path, reason } => {
+                write!(f, "could not read `{}`: {}", path, reason)
+            }
+        }
+    }
+}
+
+/// An assembly error, along with where in the source it happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub location: Location,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, location: &Location) -> Self {
+        Self {
+            kind,
+            location: location.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.kind)
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,147 @@
+use crate::{Error, ErrorKind, Location};
+
+/// A whitespace-separated word of a Tal source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token<'a> {
+    pub text: &'a str,
+    pub location: Location,
+}
+
+/// Splits a Tal source into tokens, skipping comments.
+#[derive(Debug, Clone)]
+pub struct Lexer<'a> {
+    file: &'a str,
+    source: &'a str,
+    offset: usize,
+    line: usize,
+    /// Offset of the first character of the current line
+    line_start: usize,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(file: &'a str, source: &'a str) -> Self {
+        Self {
+            file,
+            source,
+            offset: 0,
+            line: 1,
+            line_start: 0,
+        }
+    }
+
+    fn location(&self) -> Location {
+        Location {
+            file: self.file.to_string(),
+            line: self.line,
+            column: self.source[self.line_start..self.offset].chars().count() + 1,
+        }
+    }
+
+    /// Moves forward `len` bytes, keeping track of lines
+    fn advance(&mut self, len: usize) {
+        let skipped = &self.source[self.offset..self.offset + len];
+        for (i, _) in skipped.match_indices('\n') {
+            self.line += 1;
+            self.line_start = self.offset + i + 1;
+        }
+        self.offset += len;
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<Token<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let rest = &self.source[self.offset..];
+            let start = rest.find(|c: char| !c.is_whitespace())?;
+            self.advance(start);
+
+            let rest = &self.source[self.offset..];
+            let location = self.location();
+
+            if rest.starts_with('(') {
+                let Some(len) = comment_len(rest) else {
+                    self.advance(rest.len());
+                    return Some(Err(Error::new(ErrorKind::UnterminatedComment, &location)));
+                };
+                self.advance(len);
+                continue;
+            }
+
+            let len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            self.advance(len);
+            return Some(Ok(Token {
+                text: &rest[..len],
+                location,
+            }));
+        }
+    }
+}
+
+/// Returns the length of the (possibly nested) comment at the start of `s`
+fn comment_len(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(source: &str) -> Result<Vec<&str>, ErrorKind> {
+        Lexer::new("test.tal", source)
+            .map(|token| token.map(|token| token.text).map_err(|err| err.kind))
+            .collect()
+    }
+
+    #[test]
+    fn splits_tokens_on_whitespace() {
+        assert_eq!(
+            tokens("|0100 #12\n\t.Screen/x  DEO\n"),
+            Ok(vec!["|0100", "#12", ".Screen/x", "DEO"])
+        );
+    }
+
+    #[test]
+    fn skips_nested_comments() {
+        assert_eq!(
+            tokens("INC ( a ( nested ) comment ) POP (x)DUP"),
+            Ok(vec!["INC", "POP", "DUP"])
+        );
+    }
+
+    #[test]
+    fn reports_unterminated_comments() {
+        let err = Lexer::new("test.tal", "INC\n  ( oops ( ) POP")
+            .find_map(Result::err)
+            .unwrap();
+
+        assert_eq!(err.kind, ErrorKind::UnterminatedComment);
+        assert_eq!(format!("{}", err.location), "test.tal:2:3");
+    }
+
+    #[test]
+    fn tracks_token_locations() {
+        let locations: Vec<_> = Lexer::new("test.tal", "|0100\n  ( comment\n ) #12 ;é DEO")
+            .map(|token| {
+                let location = token.unwrap().location;
+                (location.line, location.column)
+            })
+            .collect();
+
+        assert_eq!(locations, vec![(1, 1), (3, 4), (3, 8), (3, 11)]);
+    }
+}
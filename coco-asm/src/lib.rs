@@ -0,0 +1,15 @@
+//! An assembler for Uxntal, the language COCO-8 ROMs are written in.
+
+mod assembler;
+mod error;
+mod lexer;
+mod program;
+
+pub use assembler::{Assembler, ROM_START};
+pub use error::{Error, ErrorKind, Location};
+pub use program::{Program, Symbol};
+
+/// Assembles a Tal source. `file` is the name errors are reported with.
+pub fn assemble(file: &str, source: &str) -> Result<Program, Error> {
+    Assembler::new().assemble(file, source)
+}
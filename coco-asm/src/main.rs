@@ -0,0 +1,100 @@
+use std::env;
+use std::fs;
+use std::process::ExitCode;
+
+use coco_asm::Assembler;
+
+const USAGE: &str = "usage: coco-asm [--sym] [-I <dir>]... <input.tal> <output.rom>
+       coco-asm --header <devices.tal>
+
+  --sym     also write a symbol file to <output.rom>.sym
+  -I <dir>  search <dir> for included files
+  --header  write a Tal file with labels for the COCO-8 device ports";
+
+#[derive(Debug, Default)]
+struct Args {
+    input: String,
+    output: String,
+    sym: bool,
+    include_paths: Vec<String>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Option<Args> {
+    let mut parsed = Args::default();
+    let mut files = vec![];
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--sym" => parsed.sym = true,
+            "-I" => parsed.include_paths.push(args.next()?),
+            _ => files.push(arg),
+        }
+    }
+
+    let [input, output] = <[String; 2]>::try_from(files).ok()?;
+    parsed.input = input;
+    parsed.output = output;
+    Some(parsed)
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let [flag, path] = args.as_slice() {
+        if flag == "--header" {
+            return write_header(path);
+        }
+    }
+
+    let Some(args) = parse_args(args.into_iter()) else {
+        eprintln!("{}", USAGE);
+        return ExitCode::FAILURE;
+    };
+
+    match assemble(&args) {
+        Ok(len) => {
+            eprintln!("Assembled {} in {} bytes.", args.output, len);
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn write_header(path: &str) -> ExitCode {
+    match fs::write(path, coco_vm::tal_header()) {
+        Ok(()) => {
+            eprintln!("Wrote device definitions to {}.", path);
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("error: could not write {}: {}", path, err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn assemble(args: &Args) -> Result<usize, Box<dyn std::error::Error>> {
+    let Args { input, output, .. } = args;
+
+    let source =
+        fs::read_to_string(input).map_err(|err| format!("could not read {}: {}", input, err))?;
+    let assembler = args
+        .include_paths
+        .iter()
+        .fold(Assembler::new(), |assembler, dir| {
+            assembler.include_path(dir)
+        });
+    let program = assembler.assemble(input, &source)?;
+    fs::write(output, &program.rom)
+        .map_err(|err| format!("could not write {}: {}", output, err))?;
+
+    if args.sym {
+        let path = format!("{}.sym", output);
+        fs::write(&path, program.sym_file())
+            .map_err(|err| format!("could not write {}: {}", path, err))?;
+    }
+
+    Ok(program.rom.len())
+}
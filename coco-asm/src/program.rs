@@ -0,0 +1,57 @@
+/// A label and the address it points to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub addr: u16,
+    pub name: String,
+}
+
+/// The output of the assembler.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Program {
+    /// The bytes of the ROM, which start at `0x100`
+    pub rom: Vec<u8>,
+    /// Every label, in the order they were defined
+    pub symbols: Vec<Symbol>,
+}
+
+impl Program {
+    /// Returns the contents of a `.sym` file, in the Uxn format: for each
+    /// symbol, its address as a big-endian short followed by its name and a
+    /// null byte
+    pub fn sym_file(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        for symbol in &self.symbols {
+            bytes.extend_from_slice(&symbol.addr.to_be_bytes());
+            bytes.extend_from_slice(symbol.name.as_bytes());
+            bytes.push(0x00);
+        }
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_uxn_symbol_files() {
+        let program = Program {
+            rom: vec![],
+            symbols: vec![
+                Symbol {
+                    addr: 0x0010,
+                    name: "Screen".to_string(),
+                },
+                Symbol {
+                    addr: 0x0112,
+                    name: "main/loop".to_string(),
+                },
+            ],
+        };
+
+        assert_eq!(
+            program.sym_file(),
+            b"\x00\x10Screen\x00\x01\x12main/loop\x00".to_vec()
+        );
+    }
+}
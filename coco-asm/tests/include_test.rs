@@ -0,0 +1,100 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use coco_asm::{Assembler, ErrorKind};
+
+/// Writes the given files into a fresh directory and returns its path
+fn project(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = fs::remove_dir_all(&dir);
+    for (path, contents) in files {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+    dir
+}
+
+fn assemble_main(assembler: Assembler, dir: &Path) -> Result<Vec<u8>, coco_asm::Error> {
+    let main = dir.join("main.tal");
+    let source = fs::read_to_string(&main).unwrap();
+    assembler
+        .assemble(&main.display().to_string(), &source)
+        .map(|program| program.rom)
+}
+
+#[test]
+fn test_includes_files_next_to_the_source() {
+    let dir = project(
+        "include_relative",
+        &[
+            ("main.tal", "~devices.tal |0100 #01 .Screen/x DEO BRK"),
+            ("devices.tal", "|10 @Screen &vector $2 &x $1"),
+        ],
+    );
+
+    let rom = assemble_main(Assembler::new(), &dir).unwrap();
+
+    assert_eq!(rom, vec![0x80, 0x01, 0x80, 0x12, 0x17, 0x00]);
+}
+
+#[test]
+fn test_searches_include_paths() {
+    let dir = project(
+        "include_paths",
+        &[
+            ("main.tal", "~macros.tal |0100 TWICE"),
+            ("lib/macros.tal", "~ops.tal %TWICE { OP OP }"),
+            ("lib/ops.tal", "%OP { INC }"),
+        ],
+    );
+
+    let rom = assemble_main(Assembler::new().include_path(dir.join("lib")), &dir).unwrap();
+
+    assert_eq!(rom, vec![0x01, 0x01]);
+}
+
+#[test]
+fn test_missing_includes_are_reported() {
+    let dir = project("include_missing", &[("main.tal", "|0100\n~nope.tal")]);
+
+    let err = assemble_main(Assembler::new(), &dir).unwrap_err();
+
+    assert_eq!(err.kind, ErrorKind::IncludeNotFound("nope.tal".to_string()));
+    assert_eq!((err.location.line, err.location.column), (2, 1));
+}
+
+#[test]
+fn test_include_cycles_are_reported() {
+    let dir = project(
+        "include_cycle",
+        &[
+            ("main.tal", "~a.tal"),
+            ("a.tal", "~b.tal"),
+            ("b.tal", "( back to a )\n~a.tal"),
+        ],
+    );
+
+    let err = assemble_main(Assembler::new(), &dir).unwrap_err();
+
+    assert_eq!(err.kind, ErrorKind::IncludeCycle("a.tal".to_string()));
+    assert!(err.location.file.ends_with("b.tal"));
+    assert_eq!(err.location.line, 2);
+}
+
+#[test]
+fn test_errors_point_to_the_included_file() {
+    let dir = project(
+        "include_errors",
+        &[
+            ("main.tal", "|0100\n~game.tal\nBRK"),
+            ("game.tal", "@main\n  ;on-frame BRK"),
+        ],
+    );
+
+    let err = assemble_main(Assembler::new(), &dir).unwrap_err();
+
+    assert_eq!(err.kind, ErrorKind::UndefinedLabel("on-frame".to_string()));
+    assert!(err.location.file.ends_with("game.tal"));
+    assert_eq!((err.location.line, err.location.column), (2, 3));
+}
@@ -0,0 +1,49 @@
+use std::fs;
+use std::path::PathBuf;
+
+fn roms_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../coco-ui/roms")
+}
+
+fn assert_assembles_to_rom(name: &str) {
+    let path = roms_dir().join(format!("{}.tal", name));
+    let source = fs::read_to_string(&path).unwrap();
+    let expected = fs::read(roms_dir().join(format!("{}.rom", name))).unwrap();
+
+    let rom = coco_asm::assemble(&path.display().to_string(), &source)
+        .unwrap()
+        .rom;
+
+    assert_eq!(rom, expected, "{}.rom differs", name);
+}
+
+#[test]
+fn test_put_pixel_rom() {
+    assert_assembles_to_rom("put_pixel");
+}
+
+#[test]
+fn test_pixel_fill_rom() {
+    assert_assembles_to_rom("pixel_fill");
+}
+
+#[test]
+fn test_sprite_rom() {
+    assert_assembles_to_rom("sprite");
+}
+
+#[test]
+fn test_pacman_rom() {
+    assert_assembles_to_rom("pacman");
+}
+
+#[test]
+fn test_devices_header_is_up_to_date() {
+    let header = fs::read_to_string(roms_dir().join("devices.tal")).unwrap();
+
+    assert_eq!(
+        header,
+        coco_vm::tal_header(),
+        "run `coco-asm --header coco-ui/roms/devices.tal`"
+    );
+}
@@ -0,0 +1,181 @@
+mod screenshot;
+
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::ExitCode;
+
+use coco_core::Cpu;
+use coco_vm::{DeviceOutput, DirectoryStorage, Vm, MAX_CYCLES_PER_VECTOR};
+
+const USAGE: &str =
+    "usage: coco-cli [--frames <n>] [--screenshot <out.png> [--layers]] [--files <dir>]
+                [--stdin] <input.rom>
+
+  --frames <n>            number of video frames to run after reset (default: 1)
+  --screenshot <out.png>  save the screen to a PNG file once the ROM stops
+  --layers                also save each layer (<out>-background.png and
+                          <out>-foreground.png)
+  --files <dir>           let the File device read and write files in <dir>
+  --stdin                 read stdin until it's closed, and send it to the
+                          Console vector after reset, one byte at a time
+
+Exit status is 0 when all frames ran, the code the ROM halted with (the
+value written to the System state port, without its top bit) when it
+halted, 1 when the ROM faulted or got stuck, and 2 for usage or I/O errors.";
+
+#[derive(Debug)]
+struct Args {
+    rom: String,
+    frames: usize,
+    screenshot: Option<String>,
+    layers: bool,
+    files: Option<String>,
+    stdin: bool,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Option<Args> {
+    let mut rom = None;
+    let mut frames = 1;
+    let mut screenshot = None;
+    let mut layers = false;
+    let mut files = None;
+    let mut stdin = false;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--frames" => frames = args.next()?.parse().ok()?,
+            "--screenshot" => screenshot = Some(args.next()?),
+            "--layers" => layers = true,
+            "--files" => files = Some(args.next()?),
+            "--stdin" => stdin = true,
+            _ if rom.is_none() => rom = Some(arg),
+            _ => return None,
+        }
+    }
+    if layers && screenshot.is_none() {
+        return None;
+    }
+
+    Some(Args {
+        rom: rom?,
+        frames,
+        screenshot,
+        layers,
+        files,
+        stdin,
+    })
+}
+
+/// Why a run stopped before going through all the frames
+#[derive(Debug)]
+enum Stop {
+    Halted(u8),
+    Failed(String),
+}
+
+fn main() -> ExitCode {
+    let Some(args) = parse_args(env::args().skip(1)) else {
+        eprintln!("{}", USAGE);
+        return ExitCode::from(2);
+    };
+    let rom = match fs::read(&args.rom) {
+        Ok(rom) => rom,
+        Err(err) => {
+            eprintln!("error: could not read {}: {}", args.rom, err);
+            return ExitCode::from(2);
+        }
+    };
+
+    let input = match read_stdin(args.stdin) {
+        Ok(input) => input,
+        Err(err) => {
+            eprintln!("error: could not read stdin: {}", err);
+            return ExitCode::from(2);
+        }
+    };
+
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new();
+    if let Some(dir) = &args.files {
+        vm = vm.with_storage(DirectoryStorage::new(dir));
+    }
+
+    let status = match run(&mut vm, &mut cpu, &input, args.frames) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(Stop::Halted(code)) => ExitCode::from(code),
+        Err(Stop::Failed(reason)) => {
+            eprintln!("error: {}", reason);
+            ExitCode::FAILURE
+        }
+    };
+
+    // screenshots are saved even after a fault, as that's when they're most
+    // useful
+    if let Some(path) = &args.screenshot {
+        if let Err(err) = screenshot::save(&mut vm, Path::new(path), args.layers) {
+            eprintln!("error: could not save {}: {}", path, err);
+            return ExitCode::from(2);
+        }
+    }
+
+    status
+}
+
+/// Returns everything on stdin, or nothing if console input wasn't enabled
+fn read_stdin(is_enabled: bool) -> io::Result<Vec<u8>> {
+    let mut input = vec![];
+    if is_enabled {
+        io::stdin().read_to_end(&mut input)?;
+    }
+    Ok(input)
+}
+
+/// Runs the reset vector, the console vector for every byte of stdin, and
+/// then the video vector once per frame
+fn run(vm: &mut Vm, cpu: &mut Cpu, input: &[u8], frames: usize) -> Result<(), Stop> {
+    let output = vm
+        .on_reset(cpu)
+        .map_err(|trap| Stop::Failed(format!("reset vector: {}", trap)))?;
+    check_output(output, cpu, "reset vector")?;
+
+    for &byte in input {
+        let output = vm
+            .on_console(cpu, byte)
+            .map_err(|trap| Stop::Failed(format!("console vector: {}", trap)))?;
+        check_output(output, cpu, "console vector")?;
+    }
+
+    for frame in 0..frames {
+        let name = format!("frame {}", frame);
+        let output = vm
+            .on_video(cpu)
+            .map_err(|trap| Stop::Failed(format!("{}: {}", name, trap)))?;
+        check_output(output, cpu, &name)?;
+    }
+
+    Ok(())
+}
+
+fn check_output(output: DeviceOutput, cpu: &Cpu, name: &str) -> Result<(), Stop> {
+    if !output.sys_stdout.is_empty() {
+        println!("{}", output.sys_stdout);
+    }
+    // there's nothing to do if the terminal is gone
+    let _ = io::stdout().write_all(&output.console_stdout);
+    let _ = io::stderr().write_all(&output.console_stderr);
+    if output.is_paused {
+        return Err(Stop::Failed(format!(
+            "{}: paused at {:04x} after {} cycles",
+            name,
+            cpu.pc(),
+            MAX_CYCLES_PER_VECTOR
+        )));
+    }
+    if output.shall_halt {
+        return Err(Stop::Halted(output.exit_code));
+    }
+
+    Ok(())
+}
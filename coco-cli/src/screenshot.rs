@@ -0,0 +1,52 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use coco_vm::{VideoBuffer, Vm, SCREEN_HEIGHT, SCREEN_WIDTH, THEME};
+
+/// Writes the composited video buffer to a PNG file. With `layers`, each
+/// layer is also written next to it (`shot-background.png` and
+/// `shot-foreground.png` for `shot.png`).
+pub fn save(vm: &mut Vm, path: &Path, layers: bool) -> Result<(), png::EncodingError> {
+    write_png(path, vm.pixels(), false)?;
+
+    if layers {
+        let [background, foreground] = vm.layers();
+        write_png(&layer_path(path, "background"), background, false)?;
+        write_png(&layer_path(path, "foreground"), foreground, true)?;
+    }
+
+    Ok(())
+}
+
+/// Writes pixels as an RGBA image using the COCO-8 palette. Color `0` is
+/// written as transparent when `is_overlay` is set, as it is in the
+/// foreground layer.
+fn write_png(
+    path: &Path,
+    pixels: &VideoBuffer,
+    is_overlay: bool,
+) -> Result<(), png::EncodingError> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut data = Vec::with_capacity(pixels.len() * 4);
+    for &pixel in pixels {
+        let (r, g, b) = THEME[pixel as usize & 0x0f];
+        let alpha = if is_overlay && pixel == 0x00 {
+            0x00
+        } else {
+            0xff
+        };
+        data.extend_from_slice(&[r, g, b, alpha]);
+    }
+
+    encoder.write_header()?.write_image_data(&data)
+}
+
+fn layer_path(path: &Path, layer: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}-{}.png", stem, layer))
+}
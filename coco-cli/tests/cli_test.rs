@@ -0,0 +1,256 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use coco_core::opcodes::*;
+use coco_vm::{SCREEN_HEIGHT, SCREEN_WIDTH, THEME};
+
+fn roms_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../coco-ui/roms")
+}
+
+/// Writes a ROM into a temporary file and returns its path
+fn rom_file(name: &str, rom: &[u8]) -> PathBuf {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    fs::write(&path, rom).unwrap();
+    path
+}
+
+fn coco_cli(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_coco-cli"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_runs_rom_frames() {
+    let rom = roms_dir().join("pacman.rom");
+
+    let output = coco_cli(&["--frames", "30", rom.to_str().unwrap()]);
+
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn test_prints_system_output() {
+    let rom = rom_file("debug.rom", &[PUSH, 0xff, PUSH, 0x01, PUSH, 0x02, DEO, BRK]);
+
+    let output = coco_cli(&[rom.to_str().unwrap()]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "WRK: [ff]\nRET: []\n"
+    );
+}
+
+#[test]
+fn test_prints_console_output() {
+    let rom = rom_file(
+        "console.rom",
+        &[
+            PUSH, b'o', PUSH, 0x28, DEO, PUSH, b'k', PUSH, 0x28, DEO, PUSH, b'!', PUSH, 0x29, DEO,
+            BRK,
+        ],
+    );
+
+    let output = coco_cli(&[rom.to_str().unwrap()]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"ok");
+    assert_eq!(output.stderr, b"!");
+}
+
+#[test]
+fn test_sends_stdin_to_console() {
+    // echoes every byte it reads, in uppercase
+    let rom = rom_file(
+        "echo.rom",
+        &[
+            PUSH2, 0x01, 0x07, PUSH, 0x20, DEO2, BRK, // set console vector to 0x0107
+            PUSH, 0x22, DEI, PUSH, 0x20, SUB, PUSH, 0x28, DEO, BRK,
+        ],
+    );
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_coco-cli"))
+        .args(["--stdin", rom.to_str().unwrap()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"coco").unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"COCO");
+}
+
+#[test]
+fn test_ignores_stdin_by_default() {
+    let rom = rom_file("ignore-stdin.rom", &[BRK]);
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_coco-cli"))
+        .arg(rom.to_str().unwrap())
+        .stdin(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // keep stdin open while the CLI runs, like a pipe that never closes
+    let _stdin = child.stdin.take().unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if Instant::now() > deadline {
+            child.kill().unwrap();
+            panic!("coco-cli waited for stdin to close");
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+
+    assert_eq!(status.code(), Some(0));
+}
+
+#[test]
+fn test_halting_stops_early() {
+    // writes a byte to the console every frame, and halts on the third one
+    let rom = rom_file(
+        "halt.rom",
+        &[
+            PUSH2, 0x01, 0x07, PUSH, 0x10, DEO2, BRK, // set screen vector to 0x0107
+            PUSH, 0x00, LDZ, INC, DUP, PUSH, 0x00, STZ, // increment counter at 0x0000
+            DUP, PUSH, b'0', ADD, PUSH, 0x28, DEO, // write it
+            PUSH, 0x03, EQU, PUSH, 0x70, SFT, PUSH, 0x0f,
+            DEO, // halt with 0 on the third frame
+            BRK,
+        ],
+    );
+
+    let output = coco_cli(&["--frames", "10", rom.to_str().unwrap()]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"123");
+}
+
+#[test]
+fn test_halting_exits_with_state() {
+    let rom = rom_file("state.rom", &[PUSH, 0x01, PUSH, 0x0f, DEO, BRK]);
+
+    let output = coco_cli(&["--frames", "10", rom.to_str().unwrap()]);
+
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn test_faults_exit_with_failure() {
+    let rom = rom_file("fault.rom", &[POP, BRK]);
+
+    let output = coco_cli(&[rom.to_str().unwrap()]);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "error: reset vector: stack underflow at 0100 (opcode 02)\n"
+    );
+}
+
+#[test]
+fn test_runaway_vectors_exit_with_failure() {
+    let rom = rom_file("runaway.rom", &[JMI, 0xff, 0xfd]);
+
+    let output = coco_cli(&[rom.to_str().unwrap()]);
+
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn test_usage_errors() {
+    assert_eq!(coco_cli(&[]).status.code(), Some(2));
+    assert_eq!(coco_cli(&["--frames", "x", "a.rom"]).status.code(), Some(2));
+    assert_eq!(coco_cli(&["does-not-exist.rom"]).status.code(), Some(2));
+}
+
+#[test]
+fn test_saves_screenshots() {
+    let rom = roms_dir().join("sprite.rom");
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let path = dir.join("sprite.png");
+
+    let output = coco_cli(&[
+        "--screenshot",
+        path.to_str().unwrap(),
+        "--layers",
+        rom.to_str().unwrap(),
+    ]);
+
+    assert_eq!(output.status.code(), Some(0));
+    for name in [
+        "sprite.png",
+        "sprite-background.png",
+        "sprite-foreground.png",
+    ] {
+        let decoder = png::Decoder::new(fs::File::open(dir.join(name)).unwrap());
+        let reader = decoder.read_info().unwrap();
+        assert_eq!(reader.info().width, SCREEN_WIDTH as u32);
+        assert_eq!(reader.info().height, SCREEN_HEIGHT as u32);
+    }
+}
+
+#[test]
+fn test_screenshots_use_the_palette() {
+    // fill the background with color 0x01, and put a 0x08 pixel at 0,0
+    let rom = rom_file(
+        "fill.rom",
+        &[
+            PUSH, 0x00, PUSH, 0x12, DEO, PUSH, 0x00, PUSH, 0x13, DEO, PUSH, 0x21, PUSH, 0x14, DEO,
+            PUSH, 0x08, PUSH, 0x14, DEO, BRK,
+        ],
+    );
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("fill.png");
+
+    coco_cli(&[
+        "--screenshot",
+        path.to_str().unwrap(),
+        rom.to_str().unwrap(),
+    ]);
+
+    let decoder = png::Decoder::new(fs::File::open(path).unwrap());
+    let mut reader = decoder.read_info().unwrap();
+    let mut data = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut data).unwrap();
+
+    let (r, g, b) = THEME[0x08];
+    assert_eq!(&data[0..4], &[r, g, b, 0xff]);
+    let (r, g, b) = THEME[0x01];
+    assert_eq!(&data[4..8], &[r, g, b, 0xff]);
+}
+
+#[test]
+fn test_layers_need_a_screenshot() {
+    assert_eq!(coco_cli(&["--layers", "a.rom"]).status.code(), Some(2));
+}
+
+#[test]
+fn test_files_are_sandboxed_in_a_directory() {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("cli_files");
+    fs::create_dir_all(&dir).unwrap();
+    // writes "ok" into a file named "out"
+    let rom = rom_file(
+        "write.rom",
+        &[
+            PUSH2, 0x01, 0x13, PUSH, 0xa8, DEO2, // name
+            PUSH2, 0x00, 0x02, PUSH, 0xaa, DEO2, // length
+            PUSH2, 0x01, 0x17, PUSH, 0xae, DEO2, // write
+            BRK, b'o', b'u', b't', 0x00, b'o', b'k',
+        ],
+    );
+
+    let output = coco_cli(&["--files", dir.to_str().unwrap(), rom.to_str().unwrap()]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(fs::read(dir.join("out")).unwrap(), b"ok");
+}
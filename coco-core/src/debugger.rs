@@ -0,0 +1,383 @@
+use core::ops::ControlFlow;
+
+use crate::{Access, Cpu, Machine, RunStatus, Trap};
+
+/// The kind of access that triggers a watchpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watch {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Watch {
+    fn on_read(&self) -> bool {
+        matches!(self, Self::Read | Self::ReadWrite)
+    }
+
+    fn on_write(&self) -> bool {
+        matches!(self, Self::Write | Self::ReadWrite)
+    }
+}
+
+/// Why a debugging session stopped running code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugStatus {
+    /// A BRK opcode was found
+    Break,
+    /// The max. number of cycles was reached before finding a BRK opcode
+    BudgetExhausted,
+    /// An instruction faulted
+    Fault(Trap),
+    /// The PC reached a breakpoint. The instruction there has not been run.
+    Breakpoint(u16),
+    /// The instruction at `pc` made an access to a watched address or port
+    Watchpoint { pc: u16, access: Access },
+    /// A hook paused execution before running the instruction at this address
+    Paused(u16),
+}
+
+/// A callback that fires before each instruction runs.
+pub trait Hook {
+    fn before_instruction(&mut self, cpu: &Cpu) -> ControlFlow<()>;
+}
+
+impl Hook for () {
+    fn before_instruction(&mut self, _: &Cpu) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+}
+
+impl<F: FnMut(&Cpu) -> ControlFlow<()>> Hook for F {
+    fn before_instruction(&mut self, cpu: &Cpu) -> ControlFlow<()> {
+        self(cpu)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct BitSet<const WORDS: usize>([u32; WORDS]);
+
+impl<const WORDS: usize> BitSet<WORDS> {
+    fn new() -> Self {
+        Self([0; WORDS])
+    }
+
+    #[inline]
+    fn contains(&self, i: u16) -> bool {
+        let i = i as usize;
+        (self.0[i / 32] & (1 << (i % 32))) != 0
+    }
+
+    fn set(&mut self, i: u16, value: bool) {
+        let i = i as usize;
+        if value {
+            self.0[i / 32] |= 1 << (i % 32);
+        } else {
+            self.0[i / 32] &= !(1 << (i % 32));
+        }
+    }
+}
+
+type RamBits = BitSet<{ 0x10000 / 32 }>;
+type PortBits = BitSet<{ 0x100 / 32 }>;
+
+/// Runs code on a CPU, stopping at breakpoints and watchpoints.
+#[derive(Debug, Clone)]
+pub struct Debugger {
+    breakpoints: RamBits,
+    ram_reads: RamBits,
+    ram_writes: RamBits,
+    port_reads: PortBits,
+    port_writes: PortBits,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self {
+            breakpoints: RamBits::new(),
+            ram_reads: RamBits::new(),
+            ram_writes: RamBits::new(),
+            port_reads: PortBits::new(),
+            port_writes: PortBits::new(),
+        }
+    }
+
+    pub fn set_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.set(addr, true);
+    }
+
+    pub fn clear_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.set(addr, false);
+    }
+
+    pub fn has_breakpoint(&self, addr: u16) -> bool {
+        self.breakpoints.contains(addr)
+    }
+
+    /// Stops execution after an instruction accesses the given RAM address
+    pub fn watch_ram(&mut self, addr: u16, watch: Watch) {
+        self.ram_reads.set(addr, watch.on_read());
+        self.ram_writes.set(addr, watch.on_write());
+    }
+
+    pub fn unwatch_ram(&mut self, addr: u16) {
+        self.ram_reads.set(addr, false);
+        self.ram_writes.set(addr, false);
+    }
+
+    /// Stops execution after an instruction accesses the given device port
+    pub fn watch_port(&mut self, port: u8, watch: Watch) {
+        self.port_reads.set(port as u16, watch.on_read());
+        self.port_writes.set(port as u16, watch.on_write());
+    }
+
+    pub fn unwatch_port(&mut self, port: u8) {
+        self.port_reads.set(port as u16, false);
+        self.port_writes.set(port as u16, false);
+    }
+
+    /// Runs the code starting at the given address, until it finds a BRK
+    /// opcode, a breakpoint or watchpoint triggers, the hook pauses
+    /// execution or `max_cycles` instructions have been executed
+    pub fn run(
+        &mut self,
+        cpu: &mut Cpu,
+        addr: u16,
+        machine: &mut impl Machine,
+        max_cycles: usize,
+        hook: &mut impl Hook,
+    ) -> DebugStatus {
+        cpu.pc = addr;
+        self.execute(cpu, machine, max_cycles, hook, true)
+    }
+
+    /// Like `run`, but carries on from the current PC. A breakpoint at the
+    /// current PC is skipped, and the hook isn't called for the instruction
+    /// there, so execution can go on after stopping at it.
+    pub fn resume(
+        &mut self,
+        cpu: &mut Cpu,
+        machine: &mut impl Machine,
+        max_cycles: usize,
+        hook: &mut impl Hook,
+    ) -> DebugStatus {
+        self.execute(cpu, machine, max_cycles, hook, false)
+    }
+
+    fn execute(
+        &mut self,
+        cpu: &mut Cpu,
+        machine: &mut impl Machine,
+        max_cycles: usize,
+        hook: &mut impl Hook,
+        stop_at_pc: bool,
+    ) -> DebugStatus {
+        for cycle in 0..max_cycles {
+            let pc = cpu.pc();
+            let can_stop = cycle > 0 || stop_at_pc;
+            if can_stop && self.breakpoints.contains(pc) {
+                return DebugStatus::Breakpoint(pc);
+            }
+            if can_stop && hook.before_instruction(cpu).is_break() {
+                return DebugStatus::Paused(pc);
+            }
+
+            match cpu.step(machine) {
+                RunStatus::Break => return DebugStatus::Break,
+                RunStatus::Fault(trap) => return DebugStatus::Fault(trap),
+                RunStatus::BudgetExhausted => {}
+            }
+
+            if let Some(access) = cpu.last_access() {
+                if self.is_watched(access) {
+                    return DebugStatus::Watchpoint { pc, access };
+                }
+            }
+        }
+
+        DebugStatus::BudgetExhausted
+    }
+
+    fn is_watched(&self, access: Access) -> bool {
+        let ram = |bits: &RamBits, addr: u16, short: bool| {
+            bits.contains(addr) || (short && bits.contains(addr.wrapping_add(1)))
+        };
+        let port = |bits: &PortBits, port: u8, short: bool| {
+            bits.contains(port as u16) || (short && bits.contains(port.wrapping_add(1) as u16))
+        };
+
+        match access {
+            Access::RamRead { addr, short, .. } => ram(&self.ram_reads, addr, short),
+            Access::RamWrite { addr, short, .. } => ram(&self.ram_writes, addr, short),
+            Access::PortRead { port: p, short, .. } => port(&self.port_reads, p, short),
+            Access::PortWrite { port: p, short, .. } => port(&self.port_writes, p, short),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opcodes::*;
+    use crate::Fault;
+
+    struct AnyMachine {}
+    impl Machine for AnyMachine {
+        fn deo(&mut self, _: &mut Cpu, _: u8) -> Result<(), Fault> {
+            Ok(())
+        }
+        fn dei(&mut self, _: &mut Cpu, _: u8) -> Result<(), Fault> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn stops_at_breakpoint() {
+        let mut cpu = Cpu::new(&[PUSH, 0x01, INC, INC, BRK]);
+        let mut debugger = Debugger::new();
+        debugger.set_breakpoint(0x103);
+
+        let status = debugger.run(&mut cpu, 0x100, &mut AnyMachine {}, 100, &mut ());
+
+        assert_eq!(status, DebugStatus::Breakpoint(0x103));
+        assert_eq!(cpu.pc(), 0x103);
+        assert_eq!(cpu.working_stack(), &[0x02]);
+    }
+
+    #[test]
+    fn resumes_past_breakpoint() {
+        let mut cpu = Cpu::new(&[PUSH, 0x01, INC, INC, BRK]);
+        let mut debugger = Debugger::new();
+        debugger.set_breakpoint(0x103);
+        debugger.run(&mut cpu, 0x100, &mut AnyMachine {}, 100, &mut ());
+
+        let status = debugger.resume(&mut cpu, &mut AnyMachine {}, 100, &mut ());
+
+        assert_eq!(status, DebugStatus::Break);
+        assert_eq!(cpu.working_stack(), &[0x03]);
+    }
+
+    #[test]
+    fn cleared_breakpoints_do_not_stop() {
+        let mut cpu = Cpu::new(&[PUSH, 0x01, INC, BRK]);
+        let mut debugger = Debugger::new();
+        debugger.set_breakpoint(0x102);
+        debugger.clear_breakpoint(0x102);
+
+        let status = debugger.run(&mut cpu, 0x100, &mut AnyMachine {}, 100, &mut ());
+
+        assert!(!debugger.has_breakpoint(0x102));
+        assert_eq!(status, DebugStatus::Break);
+    }
+
+    #[test]
+    fn stops_at_ram_write_watchpoint() {
+        let mut cpu = Cpu::new(&[PUSH, 0x01, LDZ, PUSH2, 0xab, 0xcd, PUSH, 0x00, STZ2, BRK]);
+        let mut debugger = Debugger::new();
+        debugger.watch_ram(0x01, Watch::Write);
+
+        let status = debugger.run(&mut cpu, 0x100, &mut AnyMachine {}, 100, &mut ());
+
+        assert_eq!(
+            status,
+            DebugStatus::Watchpoint {
+                pc: 0x108,
+                access: Access::RamWrite {
+                    addr: 0x00,
+                    value: 0xabcd,
+                    short: true
+                }
+            }
+        );
+    }
+
+    #[test]
+    fn stops_at_ram_read_watchpoint() {
+        let mut cpu = Cpu::new(&[PUSH2, 0x12, 0x34, LDA, BRK]);
+        let mut debugger = Debugger::new();
+        debugger.watch_ram(0x1234, Watch::ReadWrite);
+
+        let status = debugger.run(&mut cpu, 0x100, &mut AnyMachine {}, 100, &mut ());
+
+        assert!(matches!(
+            status,
+            DebugStatus::Watchpoint {
+                pc: 0x103,
+                access: Access::RamRead { addr: 0x1234, .. }
+            }
+        ));
+    }
+
+    #[test]
+    fn stops_at_port_watchpoint() {
+        let mut cpu = Cpu::new(&[PUSH, 0x12, DEI, PUSH, 0x14, DEO, BRK]);
+        let mut debugger = Debugger::new();
+        debugger.watch_port(0x12, Watch::Write);
+        debugger.watch_port(0x14, Watch::Write);
+
+        let status = debugger.run(&mut cpu, 0x100, &mut AnyMachine {}, 100, &mut ());
+
+        assert!(matches!(
+            status,
+            DebugStatus::Watchpoint {
+                pc: 0x105,
+                access: Access::PortWrite { port: 0x14, .. }
+            }
+        ));
+    }
+
+    #[test]
+    fn hooks_fire_before_each_instruction() {
+        let mut cpu = Cpu::new(&[PUSH, 0x01, INC, INC, BRK]);
+        let mut debugger = Debugger::new();
+        let mut count = 0;
+        let mut hook = |cpu: &Cpu| {
+            count += 1;
+            if cpu.working_stack() == [0x02] {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        };
+
+        let status = debugger.run(&mut cpu, 0x100, &mut AnyMachine {}, 100, &mut hook);
+
+        assert_eq!(status, DebugStatus::Paused(0x103));
+        assert_eq!(count, 3);
+    }
+
+    #[test]
+    fn resumes_past_hook_pause() {
+        let mut cpu = Cpu::new(&[PUSH, 0x01, INC, INC, BRK]);
+        let mut debugger = Debugger::new();
+        let mut hook = |cpu: &Cpu| match cpu.pc() {
+            0x103 => ControlFlow::Break(()),
+            _ => ControlFlow::Continue(()),
+        };
+        debugger.run(&mut cpu, 0x100, &mut AnyMachine {}, 100, &mut hook);
+
+        let status = debugger.resume(&mut cpu, &mut AnyMachine {}, 100, &mut hook);
+
+        assert_eq!(status, DebugStatus::Break);
+        assert_eq!(cpu.working_stack(), &[0x03]);
+    }
+
+    #[test]
+    fn reports_faults_and_exhausted_budget() {
+        let mut debugger = Debugger::new();
+
+        let mut cpu = Cpu::new(&[POP, BRK]);
+        let status = debugger.run(&mut cpu, 0x100, &mut AnyMachine {}, 100, &mut ());
+        assert!(matches!(status, DebugStatus::Fault(_)));
+
+        let mut cpu = Cpu::new(&[JMI, 0xff, 0xfd]);
+        let status = debugger.run(&mut cpu, 0x100, &mut AnyMachine {}, 100, &mut ());
+        assert_eq!(status, DebugStatus::BudgetExhausted);
+    }
+}
@@ -0,0 +1,219 @@
+use core::fmt;
+
+use crate::opcodes::{self, mnemonic};
+
+/// The immediate value that follows some opcodes in memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    /// Literal byte (LIT, LITr)
+    Byte(u8),
+    /// Literal short (LIT2, LIT2r)
+    Short(u16),
+    /// Absolute address an immediate jump (JCI, JMI, JSI) goes to
+    Address(u16),
+}
+
+/// A decoded instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+    /// Address of the opcode
+    pub addr: u16,
+    pub opcode: u8,
+    pub operand: Option<Operand>,
+}
+
+impl Instruction {
+    /// Returns the number of bytes the instruction takes in memory
+    pub fn size(&self) -> usize {
+        match self.operand {
+            None => 1,
+            Some(Operand::Byte(_)) => 2,
+            Some(Operand::Short(_)) | Some(Operand::Address(_)) => 3,
+        }
+    }
+
+    /// Returns the Tal-like source of the instruction (e.g. `#12`, `ADD2k`)
+    pub fn source(&self) -> Source {
+        Source(*self)
+    }
+}
+
+/// Displays an instruction as Tal-like source.
+#[derive(Debug, Clone, Copy)]
+pub struct Source(Instruction);
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Instruction {
+            opcode, operand, ..
+        } = self.0;
+
+        match (opcode, operand) {
+            (opcodes::PUSH, Some(Operand::Byte(x))) => write!(f, "#{:02x}", x),
+            (opcodes::PUSH2, Some(Operand::Short(x))) => write!(f, "#{:04x}", x),
+            (_, Some(Operand::Byte(x))) => write!(f, "{} {:02x}", mnemonic(opcode), x),
+            (_, Some(Operand::Short(x))) => write!(f, "{} {:04x}", mnemonic(opcode), x),
+            (_, Some(Operand::Address(x))) => write!(f, "{} {:04x}", mnemonic(opcode), x),
+            (_, None) => write!(f, "{}", mnemonic(opcode)),
+        }
+    }
+}
+
+/// Iterator over the instructions of a chunk of bytecode.
+#[derive(Debug, Clone)]
+pub struct Disassembler<'a> {
+    bytes: &'a [u8],
+    origin: u16,
+    offset: usize,
+}
+
+/// Decodes the given bytecode, which is expected to be loaded at `origin`
+/// (ROMs are loaded at 0x100).
+pub fn disassemble(bytes: &[u8], origin: u16) -> Disassembler<'_> {
+    Disassembler {
+        bytes,
+        origin,
+        offset: 0,
+    }
+}
+
+impl Disassembler<'_> {
+    fn byte_at(&self, i: usize) -> Option<u8> {
+        self.bytes.get(i).copied()
+    }
+
+    fn short_at(&self, i: usize) -> Option<u16> {
+        Some(u16::from_be_bytes([self.byte_at(i)?, self.byte_at(i + 1)?]))
+    }
+}
+
+impl Iterator for Disassembler<'_> {
+    type Item = Instruction;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let opcode = self.byte_at(self.offset)?;
+        let addr = self.origin.wrapping_add(self.offset as u16);
+
+        let operand = match opcode {
+            opcodes::PUSH | opcodes::PUSHR => self.byte_at(self.offset + 1).map(Operand::Byte),
+            opcodes::PUSH2 | opcodes::PUSH2R => self.short_at(self.offset + 1).map(Operand::Short),
+            opcodes::JCI | opcodes::JMI | opcodes::JSI => self
+                .short_at(self.offset + 1)
+                .map(|offset| Operand::Address(addr.wrapping_add(3).wrapping_add(offset))),
+            _ => None,
+        };
+
+        let instruction = Instruction {
+            addr,
+            opcode,
+            operand,
+        };
+        self.offset += instruction.size();
+
+        Some(instruction)
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04x}  {:02x}", self.addr, self.opcode)?;
+        match self.operand {
+            Some(Operand::Byte(x)) => write!(f, " {:02x}   ", x)?,
+            Some(Operand::Short(x)) => write_short(f, x)?,
+            Some(Operand::Address(addr)) => {
+                // immediate jumps store the offset from the next instruction
+                write_short(f, addr.wrapping_sub(self.addr.wrapping_add(3)))?
+            }
+            None => write!(f, "      ")?,
+        }
+
+        write!(f, "  {}", self.source())
+    }
+}
+
+fn write_short(f: &mut fmt::Formatter<'_>, x: u16) -> fmt::Result {
+    let [hi, lo] = x.to_be_bytes();
+    write!(f, " {:02x} {:02x}", hi, lo)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opcodes::*;
+
+    fn listing(bytes: &[u8]) -> Vec<String> {
+        disassemble(bytes, 0x100)
+            .map(|instruction| format!("{}", instruction.source()))
+            .collect()
+    }
+
+    #[test]
+    fn disassembles_opcodes_with_modes() {
+        let bytes = [
+            INC,
+            ADD2,
+            POP | FLAG_KEEP,
+            STH2 | FLAG_RET,
+            JMP2 | FLAG_KEEP | FLAG_RET,
+            JNZ,
+            BRK,
+        ];
+
+        assert_eq!(
+            listing(&bytes),
+            vec!["INC", "ADD2", "POPk", "STH2r", "JMP2kr", "JCN", "BRK"]
+        );
+    }
+
+    #[test]
+    fn disassembles_literals() {
+        let bytes = [
+            PUSH, 0x12, PUSH2, 0xab, 0xcd, PUSHR, 0x01, PUSH2R, 0x02, 0x03,
+        ];
+
+        assert_eq!(
+            listing(&bytes),
+            vec!["#12", "#abcd", "LITr 01", "LIT2r 0203"]
+        );
+    }
+
+    #[test]
+    fn resolves_immediate_jump_addresses() {
+        let bytes = [JCI, 0x00, 0x03, JMI, 0xff, 0xfa, JSI, 0x00, 0x00];
+
+        assert_eq!(listing(&bytes), vec!["JCI 0106", "JMI 0100", "JSI 0109"]);
+    }
+
+    #[test]
+    fn keeps_track_of_addresses() {
+        let bytes = [PUSH2, 0x01, 0x0c, PUSH, 0x10, DEO2, BRK];
+
+        let addresses: Vec<_> = disassemble(&bytes, 0x100).map(|x| x.addr).collect();
+
+        assert_eq!(addresses, vec![0x100, 0x103, 0x105, 0x106]);
+    }
+
+    #[test]
+    fn truncated_immediates_are_left_out() {
+        assert_eq!(listing(&[PUSH2, 0x01]), vec!["LIT2", "INC"]);
+    }
+
+    #[test]
+    fn displays_bytes_and_source() {
+        let bytes = [PUSH2, 0x01, 0x0c, PUSH, 0x10, DEO2, JMI, 0xff, 0xf7];
+
+        let lines: Vec<_> = disassemble(&bytes, 0x100)
+            .map(|instruction| format!("{}", instruction))
+            .collect();
+
+        assert_eq!(
+            lines,
+            vec![
+                "0100  a0 01 0c  #010c",
+                "0103  80 10     #10",
+                "0105  37        DEO2",
+                "0106  40 ff f7  JMI 0100",
+            ]
+        );
+    }
+}
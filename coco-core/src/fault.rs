@@ -0,0 +1,57 @@
+use core::fmt;
+
+/// The reasons an instruction can fail to execute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// An opcode tried to pop more values than the stack holds
+    StackUnderflow,
+    /// An opcode tried to push a value into a full stack
+    StackOverflow,
+    /// A DIV opcode was given a zero divisor
+    DivisionByZero,
+    /// A DEI/DEO opcode targeted a port with no device attached
+    UnknownDevice(u8),
+}
+
+impl Fault {
+    /// Returns the error code the System vector receives for this fault
+    pub fn code(&self) -> u8 {
+        match self {
+            Self::StackUnderflow => 0x01,
+            Self::StackOverflow => 0x02,
+            Self::DivisionByZero => 0x03,
+            Self::UnknownDevice(_) => 0x04,
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::StackUnderflow => write!(f, "stack underflow"),
+            Self::StackOverflow => write!(f, "stack overflow"),
+            Self::DivisionByZero => write!(f, "division by zero"),
+            Self::UnknownDevice(port) => write!(f, "unknown device at port {:02x}", port),
+        }
+    }
+}
+
+/// A fault, along with the instruction that raised it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trap {
+    pub fault: Fault,
+    /// Address of the faulting instruction
+    pub pc: u16,
+    /// The faulting opcode
+    pub opcode: u8,
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at {:04x} (opcode {:02x})",
+            self.fault, self.pc, self.opcode
+        )
+    }
+}
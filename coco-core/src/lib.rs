@@ -4,17 +4,28 @@
 use core::cmp;
 use core::fmt;
 
+mod debugger;
+mod fault;
 mod stack;
-use opcodes::short_mode;
+mod trace;
+use opcodes::{keep_mode, ret_mode, short_mode};
 use stack::Stack;
 
+pub mod disasm;
 pub mod opcodes;
-use opcodes::FLAG_SHORT;
+pub use debugger::{DebugStatus, Debugger, Hook, Watch};
+pub use fault::{Fault, Trap};
+use opcodes::{FLAG_KEEP, FLAG_RET, FLAG_SHORT};
+pub use trace::{StackSnapshot, TraceBuffer, TraceEntry, TraceSink, TraceWriter};
 
 /// The trait to implement for COCO virtual machines.
 pub trait Machine {
-    fn deo(&mut self, cpu: &mut Cpu, target: u8);
-    fn dei(&mut self, cpu: &mut Cpu, target: u8);
+    /// Called after the CPU writes to the port `target`. `DEO2` calls it once,
+    /// for the port of the high byte.
+    fn deo(&mut self, cpu: &mut Cpu, target: u8) -> Result<(), Fault>;
+    /// Called before the CPU reads the port `target`, so the machine can
+    /// update it. `DEI2` calls it once, for the port of the high byte.
+    fn dei(&mut self, cpu: &mut Cpu, target: u8) -> Result<(), Fault>;
 }
 
 /// The trait to implement a COCO device's ports
@@ -25,19 +36,60 @@ pub trait Ports {
 macro_rules! binary_op {
     ($self:ident, $flags:ident, $f:expr) => {
         if short_mode($flags) {
-            let b = $self.stack.pop_short();
-            let a = $self.stack.pop_short();
+            let b = $self.active_stack().pop_short()?;
+            let a = $self.active_stack().pop_short()?;
             let f: fn(u16, u16) -> u16 = $f;
-            $self.stack.push_short(f(a, b))
+            $self.active_stack().push_short(f(a, b))
         } else {
-            let b = $self.stack.pop_byte();
-            let a = $self.stack.pop_byte();
+            let b = $self.active_stack().pop_byte()?;
+            let a = $self.active_stack().pop_byte()?;
             let f: fn(u8, u8) -> u8 = $f;
-            $self.stack.push_byte(f(a, b))
+            $self.active_stack().push_byte(f(a, b))
         }
     };
 }
 
+macro_rules! comparison_op {
+    ($self:ident, $flags:ident, $f:expr) => {{
+        let res = if short_mode($flags) {
+            let b = $self.active_stack().pop_short()?;
+            let a = $self.active_stack().pop_short()?;
+            let f: fn(u16, u16) -> bool = $f;
+            f(a, b)
+        } else {
+            let b = $self.active_stack().pop_byte()?;
+            let a = $self.active_stack().pop_byte()?;
+            let f: fn(u8, u8) -> bool = $f;
+            f(a, b)
+        };
+
+        $self
+            .active_stack()
+            .push_byte(if res { 0x01 } else { 0x00 })
+    }};
+}
+
+/// How a bounded execution of code ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunStatus {
+    /// A BRK opcode was found
+    Break,
+    /// The max. number of cycles was reached before finding a BRK opcode
+    BudgetExhausted,
+    /// An instruction faulted
+    Fault(Trap),
+}
+
+/// A RAM or device access made by an instruction. Values are bytes, unless
+/// `short` is set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    RamRead { addr: u16, value: u16, short: bool },
+    RamWrite { addr: u16, value: u16, short: bool },
+    PortRead { port: u8, value: u16, short: bool },
+    PortWrite { port: u8, value: u16, short: bool },
+}
+
 /// COCO-8 CPU.
 #[derive(Debug)]
 pub struct Cpu {
@@ -51,6 +103,10 @@ pub struct Cpu {
     ret_stack: Stack,
     /// Program counter
     pc: u16,
+    /// Whether the current opcode operates on the return stack
+    ret: bool,
+    /// RAM or device access made by the last instruction
+    access: Option<Access>,
 }
 
 impl Cpu {
@@ -66,54 +122,264 @@ impl Cpu {
             stack: Stack::new(),
             ret_stack: Stack::new(),
             pc: 0,
+            ret: false,
+            access: None,
         }
     }
 
     /// Runs the code starting the PC in the given address until
-    /// it finds a BRK opcode
-    pub fn run(&mut self, addr: u16, machine: &mut impl Machine) -> u16 {
+    /// it finds a BRK opcode, or until an instruction faults
+    pub fn run(&mut self, addr: u16, machine: &mut impl Machine) -> Result<u16, Trap> {
+        self.pc = addr;
+        while !self.tick(machine)? {}
+
+        Ok(self.pc)
+    }
+
+    /// Runs the code starting the PC in the given address until it finds
+    /// a BRK opcode, an instruction faults, or `max_cycles` instructions
+    /// have been executed
+    pub fn run_for(
+        &mut self,
+        addr: u16,
+        machine: &mut impl Machine,
+        max_cycles: usize,
+    ) -> RunStatus {
+        self.pc = addr;
+        self.resume_for(machine, max_cycles)
+    }
+
+    /// Like `run`, but records every executed instruction into the sink
+    pub fn run_traced(
+        &mut self,
+        addr: u16,
+        machine: &mut impl Machine,
+        sink: &mut dyn TraceSink,
+    ) -> Result<u16, Trap> {
         self.pc = addr;
-        loop {
-            let op = self.read_byte();
-            match op {
-                opcodes::BRK => break,
-                opcodes::INC => self.op_inc(),
-                opcodes::DUP => self.op_dup(),
-                opcodes::DUP2 => self.op_dup2(),
-                opcodes::EQU => self.op_equ::<0x00>(),
-                opcodes::EQU2 => self.op_equ::<FLAG_SHORT>(),
-                opcodes::JMP => self.op_jmp(),
-                opcodes::JMP2 => self.op_jmp2(),
-                opcodes::JNZ => self.op_jnz::<0x00>(),
-                opcodes::JNZ2 => self.op_jnz::<FLAG_SHORT>(),
-                opcodes::LDZ => self.op_ldz::<0x00>(),
-                opcodes::LDZ2 => self.op_ldz::<FLAG_SHORT>(),
-                opcodes::STZ => self.op_stz::<0x00>(),
-                opcodes::STZ2 => self.op_stz::<FLAG_SHORT>(),
-                opcodes::DEI => self.op_dei(machine),
-                opcodes::DEO => self.op_deo(machine),
-                opcodes::DEO2 => self.op_deo2(machine),
-                opcodes::ADD => self.op_add::<0x00>(),
-                opcodes::ADD2 => self.op_add::<FLAG_SHORT>(),
-                opcodes::SUB => self.op_sub::<0x00>(),
-                opcodes::SUB2 => self.op_sub::<FLAG_SHORT>(),
-                opcodes::MUL => self.op_mul::<0x00>(),
-                opcodes::MUL2 => self.op_mul::<FLAG_SHORT>(),
-                opcodes::DIV => self.op_div::<0x00>(),
-                opcodes::DIV2 => self.op_div::<FLAG_SHORT>(),
-                opcodes::PUSH => self.op_push(),
-                opcodes::PUSH2 => self.op_push2(),
-                _ => {}
+        while !self.traced_tick(machine, sink)? {}
+
+        Ok(self.pc)
+    }
+
+    /// Like `run_for`, but records every executed instruction into the sink
+    pub fn run_for_traced(
+        &mut self,
+        addr: u16,
+        machine: &mut impl Machine,
+        max_cycles: usize,
+        sink: &mut dyn TraceSink,
+    ) -> RunStatus {
+        self.pc = addr;
+        self.resume(machine, max_cycles, Some(sink))
+    }
+
+    /// Like `run_for`, but carries on from the current PC. This allows
+    /// resuming code that exhausted its budget.
+    pub fn resume_for(&mut self, machine: &mut impl Machine, max_cycles: usize) -> RunStatus {
+        self.resume(machine, max_cycles, None)
+    }
+
+    fn resume(
+        &mut self,
+        machine: &mut impl Machine,
+        max_cycles: usize,
+        mut sink: Option<&mut dyn TraceSink>,
+    ) -> RunStatus {
+        for _ in 0..max_cycles {
+            let res = match sink.as_deref_mut() {
+                Some(sink) => self.traced_tick(machine, sink),
+                None => self.tick(machine),
+            };
+            match res {
+                Ok(true) => return RunStatus::Break,
+                Ok(false) => {}
+                Err(trap) => return RunStatus::Fault(trap),
             }
         }
 
-        self.pc
+        RunStatus::BudgetExhausted
+    }
+
+    /// Runs a single instruction at the current PC. Returns
+    /// `RunStatus::BudgetExhausted` when the instruction was not a BRK.
+    pub fn step(&mut self, machine: &mut impl Machine) -> RunStatus {
+        self.resume_for(machine, 1)
+    }
+
+    /// Like `tick`, but records the executed instruction into the sink
+    fn traced_tick(
+        &mut self,
+        machine: &mut impl Machine,
+        sink: &mut dyn TraceSink,
+    ) -> Result<bool, Trap> {
+        let mut entry = TraceEntry {
+            pc: self.pc,
+            opcode: self.ram_peek_byte(self.pc),
+            stack_before: StackSnapshot::new(self.working_stack()),
+            ret_stack_before: StackSnapshot::new(self.return_stack()),
+            ..Default::default()
+        };
+
+        let res = self.tick(machine);
+
+        entry.stack_after = StackSnapshot::new(self.working_stack());
+        entry.ret_stack_after = StackSnapshot::new(self.return_stack());
+        entry.access = self.access;
+        sink.record(&entry);
+
+        res
+    }
+
+    /// Fetches and executes the instruction at the PC. Returns whether
+    /// the instruction was a BRK.
+    #[inline]
+    fn tick(&mut self, machine: &mut impl Machine) -> Result<bool, Trap> {
+        let pc = self.pc;
+        let op = self.read_byte();
+        self.access = None;
+        let keep = keep_mode(op);
+        self.ret = ret_mode(op);
+        self.stack.set_keep_mode(keep && !self.ret);
+        self.ret_stack.set_keep_mode(keep && self.ret);
+
+        let res = match op {
+            opcodes::BRK => return Ok(true),
+            opcodes::JCI => self.op_jci(),
+            opcodes::JMI => self.op_jmi(),
+            opcodes::JSI => self.op_jsi(),
+            opcodes::PUSH | opcodes::PUSHR => self.op_push(),
+            opcodes::PUSH2 | opcodes::PUSH2R => self.op_push2(),
+            _ => self.execute(op & !(FLAG_KEEP | FLAG_RET), machine),
+        };
+
+        res.map_err(|fault| Trap {
+            fault,
+            pc,
+            opcode: op,
+        })?;
+
+        Ok(false)
+    }
+
+    /// Executes a regular opcode. Keep and return modes are resolved
+    /// before, so the opcode is expected without those flags.
+    #[inline]
+    fn execute(&mut self, op: u8, machine: &mut impl Machine) -> Result<(), Fault> {
+        match op {
+            opcodes::INC => self.op_inc::<0x00>(),
+            opcodes::INC2 => self.op_inc::<FLAG_SHORT>(),
+            opcodes::POP => self.op_pop::<0x00>(),
+            opcodes::POP2 => self.op_pop::<FLAG_SHORT>(),
+            opcodes::NIP => self.op_nip::<0x00>(),
+            opcodes::NIP2 => self.op_nip::<FLAG_SHORT>(),
+            opcodes::SWP => self.op_swp::<0x00>(),
+            opcodes::SWP2 => self.op_swp::<FLAG_SHORT>(),
+            opcodes::ROT => self.op_rot::<0x00>(),
+            opcodes::ROT2 => self.op_rot::<FLAG_SHORT>(),
+            opcodes::DUP => self.op_dup(),
+            opcodes::DUP2 => self.op_dup2(),
+            opcodes::OVR => self.op_ovr::<0x00>(),
+            opcodes::OVR2 => self.op_ovr::<FLAG_SHORT>(),
+            opcodes::EQU => self.op_equ::<0x00>(),
+            opcodes::EQU2 => self.op_equ::<FLAG_SHORT>(),
+            opcodes::NEQ => self.op_neq::<0x00>(),
+            opcodes::NEQ2 => self.op_neq::<FLAG_SHORT>(),
+            opcodes::GTH => self.op_gth::<0x00>(),
+            opcodes::GTH2 => self.op_gth::<FLAG_SHORT>(),
+            opcodes::LTH => self.op_lth::<0x00>(),
+            opcodes::LTH2 => self.op_lth::<FLAG_SHORT>(),
+            opcodes::JMP => self.op_jmp(),
+            opcodes::JMP2 => self.op_jmp2(),
+            opcodes::JNZ => self.op_jnz::<0x00>(),
+            opcodes::JNZ2 => self.op_jnz::<FLAG_SHORT>(),
+            opcodes::JSR => self.op_jsr::<0x00>(),
+            opcodes::JSR2 => self.op_jsr::<FLAG_SHORT>(),
+            opcodes::STH => self.op_sth::<0x00>(),
+            opcodes::STH2 => self.op_sth::<FLAG_SHORT>(),
+            opcodes::LDZ => self.op_ldz::<0x00>(),
+            opcodes::LDZ2 => self.op_ldz::<FLAG_SHORT>(),
+            opcodes::STZ => self.op_stz::<0x00>(),
+            opcodes::STZ2 => self.op_stz::<FLAG_SHORT>(),
+            opcodes::LDR => self.op_ldr::<0x00>(),
+            opcodes::LDR2 => self.op_ldr::<FLAG_SHORT>(),
+            opcodes::STR => self.op_str::<0x00>(),
+            opcodes::STR2 => self.op_str::<FLAG_SHORT>(),
+            opcodes::LDA => self.op_lda::<0x00>(),
+            opcodes::LDA2 => self.op_lda::<FLAG_SHORT>(),
+            opcodes::STA => self.op_sta::<0x00>(),
+            opcodes::STA2 => self.op_sta::<FLAG_SHORT>(),
+            opcodes::DEI => self.op_dei(machine),
+            opcodes::DEI2 => self.op_dei2(machine),
+            opcodes::DEO => self.op_deo(machine),
+            opcodes::DEO2 => self.op_deo2(machine),
+            opcodes::ADD => self.op_add::<0x00>(),
+            opcodes::ADD2 => self.op_add::<FLAG_SHORT>(),
+            opcodes::SUB => self.op_sub::<0x00>(),
+            opcodes::SUB2 => self.op_sub::<FLAG_SHORT>(),
+            opcodes::MUL => self.op_mul::<0x00>(),
+            opcodes::MUL2 => self.op_mul::<FLAG_SHORT>(),
+            opcodes::DIV => self.op_div::<0x00>(),
+            opcodes::DIV2 => self.op_div::<FLAG_SHORT>(),
+            opcodes::AND => self.op_and::<0x00>(),
+            opcodes::AND2 => self.op_and::<FLAG_SHORT>(),
+            opcodes::ORA => self.op_ora::<0x00>(),
+            opcodes::ORA2 => self.op_ora::<FLAG_SHORT>(),
+            opcodes::EOR => self.op_eor::<0x00>(),
+            opcodes::EOR2 => self.op_eor::<FLAG_SHORT>(),
+            opcodes::SFT => self.op_sft::<0x00>(),
+            opcodes::SFT2 => self.op_sft::<FLAG_SHORT>(),
+            _ => unreachable!("opcode {:02x} is handled by tick", op),
+        }
+    }
+
+    /// Replaces the contents of the working stack with the frame a fault
+    /// handler expects: the faulting address, opcode and error code
+    /// (`addr* opcode code`). The return stack is emptied too, so the
+    /// handler can make calls even after a return stack overflow.
+    pub fn load_fault_frame(&mut self, trap: &Trap) {
+        let [hi, lo] = trap.pc.to_be_bytes();
+        self.stack.clear();
+        self.ret_stack.clear();
+        for byte in [hi, lo, trap.opcode, trap.fault.code()] {
+            // the stack has just been cleared, so there is room for these
+            let _ = self.stack.push_byte(byte);
+        }
+    }
+
+    /// Returns the contents of the working stack, from bottom to top
+    pub fn working_stack(&self) -> &[u8] {
+        self.stack.as_slice()
+    }
+
+    /// Returns the contents of the return stack, from bottom to top
+    pub fn return_stack(&self) -> &[u8] {
+        self.ret_stack.as_slice()
+    }
+
+    /// Returns the RAM or device access made by the last instruction, if any
+    pub fn last_access(&self) -> Option<Access> {
+        self.access
+    }
+
+    /// Returns the value of a device port
+    #[inline]
+    pub fn device_peek_byte(&self, port: u8) -> u8 {
+        self.devices[port as usize]
     }
 
     /// Returns the requested device page
     #[inline]
     pub fn device_page<D: Ports>(&mut self) -> &mut [u8] {
-        &mut self.devices[(D::BASE as usize)..(D::BASE as usize + 0x10)]
+        self.device_page_at(D::BASE)
+    }
+
+    /// Returns the device page that starts at `base`, for devices whose
+    /// address is only known at runtime
+    #[inline]
+    pub fn device_page_at(&mut self, base: u8) -> &mut [u8] {
+        let base = (base & 0xf0) as usize;
+        &mut self.devices[base..base + 0x10]
     }
 
     /// Returns a byte of memory
@@ -147,6 +413,27 @@ impl Cpu {
         self.pc
     }
 
+    /// Returns the stack the current opcode operates on
+    #[inline]
+    fn active_stack(&mut self) -> &mut Stack {
+        if self.ret {
+            &mut self.ret_stack
+        } else {
+            &mut self.stack
+        }
+    }
+
+    /// Returns the stack the current opcode transfers values to (for STH
+    /// and JSR)
+    #[inline]
+    fn other_stack(&mut self) -> &mut Stack {
+        if self.ret {
+            &mut self.stack
+        } else {
+            &mut self.ret_stack
+        }
+    }
+
     #[inline]
     fn read_byte(&mut self) -> u8 {
         let res = self.ram[self.pc as usize];
@@ -164,159 +451,482 @@ impl Cpu {
     }
 
     #[inline]
-    fn op_inc(&mut self) {
-        let value = self.stack.pop_byte();
-        self.stack.push_byte(value.wrapping_add(1));
+    fn op_jci(&mut self) -> Result<(), Fault> {
+        let offset = self.read_short();
+        let condition = self.stack.pop_byte()?;
+        if condition != 0x00 {
+            self.pc = self.pc.wrapping_add(offset);
+        }
+
+        Ok(())
     }
 
     #[inline]
-    fn op_dup(&mut self) {
-        let value = self.stack.pop_byte();
-        self.stack.push_byte(value);
-        self.stack.push_byte(value);
+    fn op_jmi(&mut self) -> Result<(), Fault> {
+        let offset = self.read_short();
+        self.pc = self.pc.wrapping_add(offset);
+
+        Ok(())
     }
 
     #[inline]
-    fn op_dup2(&mut self) {
-        let value = self.stack.pop_short();
-        self.stack.push_short(value);
-        self.stack.push_short(value);
+    fn op_jsi(&mut self) -> Result<(), Fault> {
+        let offset = self.read_short();
+        self.ret_stack.push_short(self.pc)?;
+        self.pc = self.pc.wrapping_add(offset);
+
+        Ok(())
     }
 
     #[inline]
-    fn op_equ<const FLAGS: u8>(&mut self) {
-        let res = if short_mode(FLAGS) {
-            let b = self.stack.pop_short();
-            let a = self.stack.pop_short();
-            a == b
+    fn op_inc<const FLAGS: u8>(&mut self) -> Result<(), Fault> {
+        if short_mode(FLAGS) {
+            let value = self.active_stack().pop_short()?;
+            self.active_stack().push_short(value.wrapping_add(1))?;
         } else {
-            let b = self.stack.pop_byte();
-            let a = self.stack.pop_byte();
-            a == b
-        };
+            let value = self.active_stack().pop_byte()?;
+            self.active_stack().push_byte(value.wrapping_add(1))?;
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    fn op_pop<const FLAGS: u8>(&mut self) -> Result<(), Fault> {
+        if short_mode(FLAGS) {
+            self.active_stack().pop_short()?;
+        } else {
+            self.active_stack().pop_byte()?;
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    fn op_nip<const FLAGS: u8>(&mut self) -> Result<(), Fault> {
+        if short_mode(FLAGS) {
+            let b = self.active_stack().pop_short()?;
+            self.active_stack().pop_short()?;
+            self.active_stack().push_short(b)?;
+        } else {
+            let b = self.active_stack().pop_byte()?;
+            self.active_stack().pop_byte()?;
+            self.active_stack().push_byte(b)?;
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    fn op_swp<const FLAGS: u8>(&mut self) -> Result<(), Fault> {
+        if short_mode(FLAGS) {
+            let b = self.active_stack().pop_short()?;
+            let a = self.active_stack().pop_short()?;
+            self.active_stack().push_short(b)?;
+            self.active_stack().push_short(a)?;
+        } else {
+            let b = self.active_stack().pop_byte()?;
+            let a = self.active_stack().pop_byte()?;
+            self.active_stack().push_byte(b)?;
+            self.active_stack().push_byte(a)?;
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    fn op_rot<const FLAGS: u8>(&mut self) -> Result<(), Fault> {
+        if short_mode(FLAGS) {
+            let c = self.active_stack().pop_short()?;
+            let b = self.active_stack().pop_short()?;
+            let a = self.active_stack().pop_short()?;
+            self.active_stack().push_short(b)?;
+            self.active_stack().push_short(c)?;
+            self.active_stack().push_short(a)?;
+        } else {
+            let c = self.active_stack().pop_byte()?;
+            let b = self.active_stack().pop_byte()?;
+            let a = self.active_stack().pop_byte()?;
+            self.active_stack().push_byte(b)?;
+            self.active_stack().push_byte(c)?;
+            self.active_stack().push_byte(a)?;
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    fn op_dup(&mut self) -> Result<(), Fault> {
+        let value = self.active_stack().pop_byte()?;
+        self.active_stack().push_byte(value)?;
+        self.active_stack().push_byte(value)?;
+
+        Ok(())
+    }
+
+    #[inline]
+    fn op_dup2(&mut self) -> Result<(), Fault> {
+        let value = self.active_stack().pop_short()?;
+        self.active_stack().push_short(value)?;
+        self.active_stack().push_short(value)?;
+
+        Ok(())
+    }
+
+    #[inline]
+    fn op_ovr<const FLAGS: u8>(&mut self) -> Result<(), Fault> {
+        if short_mode(FLAGS) {
+            let b = self.active_stack().pop_short()?;
+            let a = self.active_stack().pop_short()?;
+            self.active_stack().push_short(a)?;
+            self.active_stack().push_short(b)?;
+            self.active_stack().push_short(a)?;
+        } else {
+            let b = self.active_stack().pop_byte()?;
+            let a = self.active_stack().pop_byte()?;
+            self.active_stack().push_byte(a)?;
+            self.active_stack().push_byte(b)?;
+            self.active_stack().push_byte(a)?;
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    fn op_equ<const FLAGS: u8>(&mut self) -> Result<(), Fault> {
+        comparison_op!(self, FLAGS, |a, b| a == b)
+    }
+
+    #[inline]
+    fn op_neq<const FLAGS: u8>(&mut self) -> Result<(), Fault> {
+        comparison_op!(self, FLAGS, |a, b| a != b)
+    }
 
-        self.stack.push_byte(if res { 0x01 } else { 0x00 });
+    #[inline]
+    fn op_gth<const FLAGS: u8>(&mut self) -> Result<(), Fault> {
+        comparison_op!(self, FLAGS, |a, b| a > b)
+    }
+
+    #[inline]
+    fn op_lth<const FLAGS: u8>(&mut self) -> Result<(), Fault> {
+        comparison_op!(self, FLAGS, |a, b| a < b)
     }
 
     #[inline]
-    fn op_jmp(&mut self) {
-        let offset = self.stack.pop_byte();
-        self.pc = self.pc.wrapping_add(offset as u16);
+    fn op_jmp(&mut self) -> Result<(), Fault> {
+        let offset = self.active_stack().pop_byte()?;
+        self.pc = relative(self.pc, offset);
+
+        Ok(())
     }
 
     #[inline]
-    fn op_jmp2(&mut self) {
-        let addr = self.stack.pop_short();
+    fn op_jmp2(&mut self) -> Result<(), Fault> {
+        let addr = self.active_stack().pop_short()?;
         self.pc = addr;
+
+        Ok(())
     }
 
     #[inline]
-    fn op_jnz<const FLAGS: u8>(&mut self) {
+    fn op_jnz<const FLAGS: u8>(&mut self) -> Result<(), Fault> {
         let addr = if short_mode(FLAGS) {
-            self.stack.pop_short()
+            self.active_stack().pop_short()?
         } else {
-            let offset = self.stack.pop_byte();
-            self.pc.wrapping_add(offset as u16)
+            let offset = self.active_stack().pop_byte()?;
+            relative(self.pc, offset)
         };
 
-        let condition = self.stack.pop_byte();
+        let condition = self.active_stack().pop_byte()?;
         if condition != 0x00 {
             self.pc = addr;
         }
+
+        Ok(())
     }
 
     #[inline]
-    fn op_ldz<const FLAGS: u8>(&mut self) {
-        let addr = self.stack.pop_byte();
-        if short_mode(FLAGS) {
-            let value = self.ram_peek_short(addr as u16);
-            self.stack.push_short(value);
+    fn op_jsr<const FLAGS: u8>(&mut self) -> Result<(), Fault> {
+        let addr = if short_mode(FLAGS) {
+            self.active_stack().pop_short()?
         } else {
-            let value = self.ram_peek_byte(addr as u16);
-            self.stack.push_byte(value);
-        }
+            let offset = self.active_stack().pop_byte()?;
+            relative(self.pc, offset)
+        };
+
+        let pc = self.pc;
+        self.other_stack().push_short(pc)?;
+        self.pc = addr;
+
+        Ok(())
     }
 
     #[inline]
-    fn op_stz<const FLAGS: u8>(&mut self) {
-        let addr = self.stack.pop_byte();
+    fn op_sth<const FLAGS: u8>(&mut self) -> Result<(), Fault> {
         if short_mode(FLAGS) {
-            let value = self.stack.pop_short();
-            self.ram_poke_short(addr as u16, value);
+            let value = self.active_stack().pop_short()?;
+            self.other_stack().push_short(value)?;
         } else {
-            let value = self.stack.pop_byte();
-            self.ram_poke_byte(addr as u16, value);
+            let value = self.active_stack().pop_byte()?;
+            self.other_stack().push_byte(value)?;
         }
+
+        Ok(())
+    }
+
+    #[inline]
+    fn op_ldz<const FLAGS: u8>(&mut self) -> Result<(), Fault> {
+        let addr = self.active_stack().pop_byte()?;
+        self.load::<FLAGS>(addr as u16)?;
+
+        Ok(())
+    }
+
+    #[inline]
+    fn op_stz<const FLAGS: u8>(&mut self) -> Result<(), Fault> {
+        let addr = self.active_stack().pop_byte()?;
+        self.store::<FLAGS>(addr as u16)?;
+
+        Ok(())
+    }
+
+    #[inline]
+    fn op_ldr<const FLAGS: u8>(&mut self) -> Result<(), Fault> {
+        let offset = self.active_stack().pop_byte()?;
+        let addr = relative(self.pc, offset);
+        self.load::<FLAGS>(addr)?;
+
+        Ok(())
+    }
+
+    #[inline]
+    fn op_str<const FLAGS: u8>(&mut self) -> Result<(), Fault> {
+        let offset = self.active_stack().pop_byte()?;
+        let addr = relative(self.pc, offset);
+        self.store::<FLAGS>(addr)?;
+
+        Ok(())
+    }
+
+    #[inline]
+    fn op_lda<const FLAGS: u8>(&mut self) -> Result<(), Fault> {
+        let addr = self.active_stack().pop_short()?;
+        self.load::<FLAGS>(addr)?;
+
+        Ok(())
+    }
+
+    #[inline]
+    fn op_sta<const FLAGS: u8>(&mut self) -> Result<(), Fault> {
+        let addr = self.active_stack().pop_short()?;
+        self.store::<FLAGS>(addr)?;
+
+        Ok(())
+    }
+
+    /// Pushes the byte or short at the given address into the stack
+    #[inline]
+    fn load<const FLAGS: u8>(&mut self, addr: u16) -> Result<(), Fault> {
+        let short = short_mode(FLAGS);
+        let value = if short {
+            let value = self.ram_peek_short(addr);
+            self.active_stack().push_short(value)?;
+            value
+        } else {
+            let value = self.ram_peek_byte(addr);
+            self.active_stack().push_byte(value)?;
+            value as u16
+        };
+
+        self.access = Some(Access::RamRead { addr, value, short });
+        Ok(())
+    }
+
+    /// Pops a byte or short from the stack and writes it at the given address
+    #[inline]
+    fn store<const FLAGS: u8>(&mut self, addr: u16) -> Result<(), Fault> {
+        let short = short_mode(FLAGS);
+        let value = if short {
+            let value = self.active_stack().pop_short()?;
+            self.ram_poke_short(addr, value);
+            value
+        } else {
+            let value = self.active_stack().pop_byte()?;
+            self.ram_poke_byte(addr, value);
+            value as u16
+        };
+
+        self.access = Some(Access::RamWrite { addr, value, short });
+        Ok(())
     }
 
     #[inline]
-    fn op_push(&mut self) {
+    fn op_push(&mut self) -> Result<(), Fault> {
         let value = self.read_byte();
-        self.stack.push_byte(value);
+        self.active_stack().push_byte(value)?;
+
+        Ok(())
     }
 
     #[inline]
-    fn op_push2(&mut self) {
+    fn op_push2(&mut self) -> Result<(), Fault> {
         let value = self.read_short();
-        self.stack.push_short(value);
+        self.active_stack().push_short(value)?;
+
+        Ok(())
     }
 
     #[inline]
-    fn op_dei(&mut self, machine: &mut impl Machine) {
-        let target = self.stack.pop_byte();
-        self.stack.push_byte(self.devices[target as usize]);
+    fn op_dei(&mut self, machine: &mut impl Machine) -> Result<(), Fault> {
+        let target = self.active_stack().pop_byte()?;
 
-        // callback for I/O
-        machine.dei(self, target);
+        // callback for I/O, so the device can update the port
+        machine.dei(self, target)?;
+
+        let value = self.devices[target as usize];
+        self.active_stack().push_byte(value)?;
+        self.access = Some(Access::PortRead {
+            port: target,
+            value: value as u16,
+            short: false,
+        });
+
+        Ok(())
     }
 
     #[inline]
-    fn op_deo(&mut self, machine: &mut impl Machine) {
-        let target = self.stack.pop_byte();
+    fn op_dei2(&mut self, machine: &mut impl Machine) -> Result<(), Fault> {
+        let target = self.active_stack().pop_byte()?;
+
+        // callback for I/O, so the device can update the port
+        machine.dei(self, target)?;
+
+        let hi = self.devices[target as usize];
+        let lo = self.devices[target.wrapping_add(1) as usize];
+        let value = u16::from_be_bytes([hi, lo]);
+        self.active_stack().push_short(value)?;
+        self.access = Some(Access::PortRead {
+            port: target,
+            value,
+            short: true,
+        });
+
+        Ok(())
+    }
+
+    #[inline]
+    fn op_deo(&mut self, machine: &mut impl Machine) -> Result<(), Fault> {
+        let target = self.active_stack().pop_byte()?;
 
         // write value to device port
-        let value = self.stack.pop_byte();
+        let value = self.active_stack().pop_byte()?;
         self.devices[target as usize] = value;
+        self.access = Some(Access::PortWrite {
+            port: target,
+            value: value as u16,
+            short: false,
+        });
 
         // callback for I/O
-        machine.deo(self, target);
+        machine.deo(self, target)?;
+
+        Ok(())
     }
 
     #[inline]
-    fn op_deo2(&mut self, machine: &mut impl Machine) {
-        let target = self.stack.pop_byte();
+    fn op_deo2(&mut self, machine: &mut impl Machine) -> Result<(), Fault> {
+        let target = self.active_stack().pop_byte()?;
 
         // write short value to device port
-        let value = self.stack.pop_short();
+        let value = self.active_stack().pop_short()?;
         let [hi, lo] = value.to_be_bytes();
         self.devices[target as usize] = hi;
         self.devices[target.wrapping_add(1) as usize] = lo;
+        self.access = Some(Access::PortWrite {
+            port: target,
+            value,
+            short: true,
+        });
 
         // callback for I/0
-        machine.deo(self, target);
+        machine.deo(self, target)?;
+
+        Ok(())
     }
 
     #[inline]
-    fn op_add<const FLAGS: u8>(&mut self) {
+    fn op_add<const FLAGS: u8>(&mut self) -> Result<(), Fault> {
         binary_op!(self, FLAGS, |a, b| a.wrapping_add(b))
     }
 
     #[inline]
-    fn op_sub<const FLAGS: u8>(&mut self) {
+    fn op_sub<const FLAGS: u8>(&mut self) -> Result<(), Fault> {
         binary_op!(self, FLAGS, |a, b| a.wrapping_sub(b))
     }
 
     #[inline]
-    fn op_mul<const FLAGS: u8>(&mut self) {
+    fn op_mul<const FLAGS: u8>(&mut self) -> Result<(), Fault> {
         binary_op!(self, FLAGS, |a, b| a.wrapping_mul(b))
     }
 
     #[inline]
-    fn op_div<const FLAGS: u8>(&mut self) {
-        binary_op!(self, FLAGS, |a, b| a.wrapping_div(b))
+    fn op_div<const FLAGS: u8>(&mut self) -> Result<(), Fault> {
+        if short_mode(FLAGS) {
+            let b = self.active_stack().pop_short()?;
+            let a = self.active_stack().pop_short()?;
+            let res = a.checked_div(b).ok_or(Fault::DivisionByZero)?;
+            self.active_stack().push_short(res)
+        } else {
+            let b = self.active_stack().pop_byte()?;
+            let a = self.active_stack().pop_byte()?;
+            let res = a.checked_div(b).ok_or(Fault::DivisionByZero)?;
+            self.active_stack().push_byte(res)
+        }
+    }
+
+    #[inline]
+    fn op_and<const FLAGS: u8>(&mut self) -> Result<(), Fault> {
+        binary_op!(self, FLAGS, |a, b| a & b)
+    }
+
+    #[inline]
+    fn op_ora<const FLAGS: u8>(&mut self) -> Result<(), Fault> {
+        binary_op!(self, FLAGS, |a, b| a | b)
+    }
+
+    #[inline]
+    fn op_eor<const FLAGS: u8>(&mut self) -> Result<(), Fault> {
+        binary_op!(self, FLAGS, |a, b| a ^ b)
+    }
+
+    #[inline]
+    fn op_sft<const FLAGS: u8>(&mut self) -> Result<(), Fault> {
+        let shift = self.active_stack().pop_byte()?;
+        let right = (shift & 0x0f) as u32;
+        let left = (shift >> 4) as u32;
+
+        if short_mode(FLAGS) {
+            let value = self.active_stack().pop_short()?;
+            let res = value.checked_shr(right).unwrap_or(0);
+            self.active_stack()
+                .push_short(res.checked_shl(left).unwrap_or(0))?;
+        } else {
+            let value = self.active_stack().pop_byte()?;
+            let res = value.checked_shr(right).unwrap_or(0);
+            self.active_stack()
+                .push_byte(res.checked_shl(left).unwrap_or(0))?;
+        }
+
+        Ok(())
     }
 }
 
+/// Returns the address resulting of applying a signed, relative offset to the given one
+#[inline]
+fn relative(addr: u16, offset: u8) -> u16 {
+    addr.wrapping_add(offset as i8 as u16)
+}
+
 impl fmt::Display for Cpu {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "WRK: [{}]", self.stack)?;
@@ -331,8 +941,12 @@ mod tests {
 
     pub struct AnyMachine {}
     impl Machine for AnyMachine {
-        fn deo(&mut self, _: &mut Cpu, _: u8) {}
-        fn dei(&mut self, _: &mut Cpu, _: u8) {}
+        fn deo(&mut self, _: &mut Cpu, _: u8) -> Result<(), Fault> {
+            Ok(())
+        }
+        fn dei(&mut self, _: &mut Cpu, _: u8) -> Result<(), Fault> {
+            Ok(())
+        }
     }
 
     fn zeroed_memory() -> [u8; 0x10000 - 0x100] {
@@ -357,71 +971,296 @@ mod tests {
     pub fn runs_until_break() {
         let rom = rom_from(&[0x01, 0x01, BRK]);
         let mut cpu = Cpu::new(&rom);
+        cpu.stack.push_byte(0x00).unwrap();
 
-        let pc = cpu.run(0x100, &mut AnyMachine {});
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
 
         assert_eq!(pc, 0x103);
         assert_eq!(pc, cpu.pc);
     }
 
+    #[test]
+    fn device_page_at_returns_the_whole_page() {
+        let rom = zeroed_memory();
+        let mut cpu = Cpu::new(&rom);
+
+        cpu.device_page_at(0x7c)[0x0f] = 0xab;
+
+        assert_eq!(cpu.device_page_at(0x70).len(), 0x10);
+        assert_eq!(cpu.device_peek_byte(0x7f), 0xab);
+    }
+
+    #[test]
+    fn every_opcode_is_handled() {
+        for op in 0x00..=0xff {
+            let rom = rom_from(&[op, BRK]);
+            let mut cpu = Cpu::new(&rom);
+            for _ in 0..4 {
+                cpu.stack.push_short(0x0000).unwrap();
+                cpu.ret_stack.push_short(0x0000).unwrap();
+            }
+
+            cpu.run_for(0x100, &mut AnyMachine {}, 1);
+        }
+    }
+
     #[test]
     fn run_wraps_pc_at_the_end_of_ram() {
         let mut rom = zeroed_memory();
         rom[rom.len() - 1] = 0x01;
         let mut cpu = Cpu::new(&rom);
+        cpu.stack.push_byte(0x00).unwrap();
 
-        let pc = cpu.run(0xffff, &mut AnyMachine {});
+        let pc = cpu.run(0xffff, &mut AnyMachine {}).unwrap();
 
         assert_eq!(pc, 0x01);
         assert_eq!(pc, cpu.pc);
     }
 
     #[test]
-    fn inc_opcode() {
-        let rom = rom_from(&[PUSH, 0xff, INC, BRK]);
+    fn records_ram_accesses() {
+        let rom = rom_from(&[
+            PUSH2,
+            0xab,
+            0xcd,
+            PUSH2,
+            0x12,
+            0x34,
+            STA2 | FLAG_KEEP,
+            LDA,
+            BRK,
+        ]);
         let mut cpu = Cpu::new(&rom);
+        cpu.pc = 0x100;
+
+        cpu.step(&mut AnyMachine {});
+        assert_eq!(cpu.last_access(), None);
+        cpu.step(&mut AnyMachine {});
+        cpu.step(&mut AnyMachine {});
+        assert_eq!(
+            cpu.last_access(),
+            Some(Access::RamWrite {
+                addr: 0x1234,
+                value: 0xabcd,
+                short: true
+            })
+        );
+        cpu.step(&mut AnyMachine {});
+        assert_eq!(
+            cpu.last_access(),
+            Some(Access::RamRead {
+                addr: 0x1234,
+                value: 0xab,
+                short: false
+            })
+        );
+    }
 
-        let pc = cpu.run(0x100, &mut AnyMachine {});
-
-        assert_eq!(pc, 0x104);
-        assert_eq!(cpu.stack.len(), 1);
-        assert_eq!(cpu.stack.byte_at(0), 0x00);
+    #[test]
+    fn records_device_accesses() {
+        let rom = rom_from(&[PUSH, 0xab, PUSH, 0x12, DEO, PUSH, 0x12, DEI, BRK]);
+        let mut cpu = Cpu::new(&rom);
+        cpu.pc = 0x100;
+
+        cpu.resume_for(&mut AnyMachine {}, 3);
+        assert_eq!(
+            cpu.last_access(),
+            Some(Access::PortWrite {
+                port: 0x12,
+                value: 0xab,
+                short: false
+            })
+        );
+        cpu.resume_for(&mut AnyMachine {}, 2);
+        assert_eq!(
+            cpu.last_access(),
+            Some(Access::PortRead {
+                port: 0x12,
+                value: 0xab,
+                short: false
+            })
+        );
     }
 
     #[test]
-    fn dup_opcode() {
-        let rom = rom_from(&[PUSH, 0xab, DUP, BRK]);
+    fn exposes_stack_contents() {
+        let rom = rom_from(&[PUSH2, 0xab, 0xcd, PUSHR, 0x01, BRK]);
         let mut cpu = Cpu::new(&rom);
 
-        let pc = cpu.run(0x100, &mut AnyMachine {});
+        cpu.run(0x100, &mut AnyMachine {}).unwrap();
 
-        assert_eq!(pc, 0x104);
-        assert_eq!(cpu.stack.len(), 2);
-        assert_eq!(cpu.stack.byte_at(0), 0xab);
-        assert_eq!(cpu.stack.byte_at(1), 0xab);
+        assert_eq!(cpu.working_stack(), &[0xab, 0xcd]);
+        assert_eq!(cpu.return_stack(), &[0x01]);
     }
 
     #[test]
-    fn dup2_opcode() {
-        let rom = rom_from(&[PUSH2, 0xab, 0xcb, DUP2, BRK]);
-        let mut cpu = Cpu::new(&rom);
+    fn mnemonics_include_mode_suffixes() {
+        assert_eq!(format!("{}", mnemonic(BRK)), "BRK");
+        assert_eq!(format!("{}", mnemonic(JCI)), "JCI");
+        assert_eq!(format!("{}", mnemonic(PUSH)), "LIT");
+        assert_eq!(format!("{}", mnemonic(PUSH2R)), "LIT2r");
+        assert_eq!(format!("{}", mnemonic(JNZ)), "JCN");
+        assert_eq!(
+            format!("{}", mnemonic(ADD2 | FLAG_KEEP | FLAG_RET)),
+            "ADD2kr"
+        );
+        assert_eq!(format!("{:<5}|", mnemonic(INC | FLAG_RET)), "INCr |");
+    }
 
-        let pc = cpu.run(0x100, &mut AnyMachine {});
+    #[test]
+    fn mnemonics_map_back_to_opcodes() {
+        for opcode in 0x00..=0xff {
+            let name = format!("{}", mnemonic(opcode));
+            assert_eq!(from_mnemonic(&name), Some(opcode), "{}", name);
+        }
 
-        assert_eq!(pc, 0x105);
-        assert_eq!(cpu.stack.len(), 4);
-        assert_eq!(cpu.stack.short_at(0), 0xabcb);
-        assert_eq!(cpu.stack.short_at(2), 0xabcb);
+        assert_eq!(from_mnemonic("ADDk2"), Some(ADD2 | FLAG_KEEP));
+        assert_eq!(from_mnemonic("ADD22"), None);
+        assert_eq!(from_mnemonic("BRKk"), None);
+        assert_eq!(from_mnemonic("ADDx"), None);
+        assert_eq!(from_mnemonic("AD"), None);
+        assert_eq!(from_mnemonic("FOO"), None);
     }
 
     #[test]
-    fn push_opcode() {
-        let rom = rom_from(&[PUSH, 0xab, BRK]);
+    fn steps_one_instruction() {
+        let rom = rom_from(&[PUSH, 0x01, INC, BRK]);
         let mut cpu = Cpu::new(&rom);
+        cpu.pc = 0x100;
 
-        let pc = cpu.run(0x100, &mut AnyMachine {});
-
-        assert_eq!(pc, 0x103);
+        assert_eq!(cpu.step(&mut AnyMachine {}), RunStatus::BudgetExhausted);
+        assert_eq!(cpu.pc, 0x102);
+        assert_eq!(cpu.stack.len(), 1);
+        assert_eq!(cpu.stack.byte_at(0), 0x01);
+
+        assert_eq!(cpu.step(&mut AnyMachine {}), RunStatus::BudgetExhausted);
+        assert_eq!(cpu.pc, 0x103);
+        assert_eq!(cpu.stack.byte_at(0), 0x02);
+
+        assert_eq!(cpu.step(&mut AnyMachine {}), RunStatus::Break);
+        assert_eq!(cpu.pc, 0x104);
+    }
+
+    #[test]
+    fn step_reports_faults() {
+        let rom = rom_from(&[POP, BRK]);
+        let mut cpu = Cpu::new(&rom);
+        cpu.pc = 0x100;
+
+        let status = cpu.step(&mut AnyMachine {});
+
+        assert_eq!(
+            status,
+            RunStatus::Fault(Trap {
+                fault: Fault::StackUnderflow,
+                pc: 0x100,
+                opcode: POP
+            })
+        );
+    }
+
+    #[test]
+    fn run_for_stops_at_break() {
+        let rom = rom_from(&[PUSH, 0x01, INC, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let status = cpu.run_for(0x100, &mut AnyMachine {}, 10);
+
+        assert_eq!(status, RunStatus::Break);
+        assert_eq!(cpu.pc, 0x104);
+        assert_eq!(cpu.stack.byte_at(0), 0x02);
+    }
+
+    #[test]
+    fn run_for_stops_when_budget_is_exhausted() {
+        // infinite loop: JMI -3
+        let rom = rom_from(&[JMI, 0xff, 0xfd]);
+        let mut cpu = Cpu::new(&rom);
+
+        let status = cpu.run_for(0x100, &mut AnyMachine {}, 1000);
+
+        assert_eq!(status, RunStatus::BudgetExhausted);
+        assert_eq!(cpu.pc, 0x100);
+    }
+
+    #[test]
+    fn resume_for_carries_on_from_the_pc() {
+        let rom = rom_from(&[PUSH, 0x01, INC, INC, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let status = cpu.run_for(0x100, &mut AnyMachine {}, 2);
+        assert_eq!(status, RunStatus::BudgetExhausted);
+        assert_eq!(cpu.pc, 0x103);
+
+        let status = cpu.resume_for(&mut AnyMachine {}, 2);
+        assert_eq!(status, RunStatus::Break);
+        assert_eq!(cpu.pc, 0x105);
+        assert_eq!(cpu.stack.byte_at(0), 0x03);
+    }
+
+    #[test]
+    fn run_for_reports_faults() {
+        let rom = rom_from(&[PUSH, 0x01, PUSH, 0x00, DIV, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let status = cpu.run_for(0x100, &mut AnyMachine {}, 10);
+
+        assert_eq!(
+            status,
+            RunStatus::Fault(Trap {
+                fault: Fault::DivisionByZero,
+                pc: 0x104,
+                opcode: DIV
+            })
+        );
+    }
+
+    #[test]
+    fn inc_opcode() {
+        let rom = rom_from(&[PUSH, 0xff, INC, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x104);
+        assert_eq!(cpu.stack.len(), 1);
+        assert_eq!(cpu.stack.byte_at(0), 0x00);
+    }
+
+    #[test]
+    fn dup_opcode() {
+        let rom = rom_from(&[PUSH, 0xab, DUP, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x104);
+        assert_eq!(cpu.stack.len(), 2);
+        assert_eq!(cpu.stack.byte_at(0), 0xab);
+        assert_eq!(cpu.stack.byte_at(1), 0xab);
+    }
+
+    #[test]
+    fn dup2_opcode() {
+        let rom = rom_from(&[PUSH2, 0xab, 0xcb, DUP2, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x105);
+        assert_eq!(cpu.stack.len(), 4);
+        assert_eq!(cpu.stack.short_at(0), 0xabcb);
+        assert_eq!(cpu.stack.short_at(2), 0xabcb);
+    }
+
+    #[test]
+    fn push_opcode() {
+        let rom = rom_from(&[PUSH, 0xab, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x103);
         assert_eq!(cpu.stack.len(), 1);
         assert_eq!(cpu.stack.byte_at(0), 0xab);
     }
@@ -431,7 +1270,7 @@ mod tests {
         let rom = rom_from(&[PUSH2, 0xab, 0xcd, BRK]);
         let mut cpu = Cpu::new(&rom);
 
-        let pc = cpu.run(0x100, &mut AnyMachine {});
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
 
         assert_eq!(pc, 0x104);
         assert_eq!(cpu.stack.len(), 2);
@@ -444,7 +1283,7 @@ mod tests {
         let mut cpu = Cpu::new(&rom);
         cpu.devices[0x10] = 0xab;
 
-        let pc = cpu.run(0x100, &mut AnyMachine {});
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
 
         assert_eq!(pc, 0x104);
         assert_eq!(cpu.stack.len(), 1);
@@ -456,7 +1295,7 @@ mod tests {
         let rom = rom_from(&[PUSH, 0xab, PUSH, 0x02, DEO, BRK]);
         let mut cpu = Cpu::new(&rom);
 
-        let pc = cpu.run(0x100, &mut AnyMachine {});
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
 
         assert_eq!(pc, 0x106);
         assert_eq!(cpu.stack.len(), 0);
@@ -469,7 +1308,7 @@ mod tests {
         let rom = rom_from(&[PUSH2, 0xab, 0xcd, PUSH, 0x00, DEO2, BRK]);
         let mut cpu = Cpu::new(&rom);
 
-        let pc = cpu.run(0x100, &mut AnyMachine {});
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
 
         assert_eq!(pc, 0x107);
         assert_eq!(cpu.stack.len(), 0);
@@ -483,7 +1322,7 @@ mod tests {
         let rom = rom_from(&[PUSH, 0xab, PUSH, 0x02, ADD, BRK]);
         let mut cpu = Cpu::new(&rom);
 
-        let pc = cpu.run(0x100, &mut AnyMachine {});
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
 
         assert_eq!(pc, 0x106);
         assert_eq!(cpu.stack.len(), 1);
@@ -495,7 +1334,7 @@ mod tests {
         let rom = rom_from(&[PUSH2, 0xab, 0xcd, PUSH2, 0x11, 0x11, ADD2, BRK]);
         let mut cpu = Cpu::new(&rom);
 
-        let pc = cpu.run(0x100, &mut AnyMachine {});
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
 
         assert_eq!(pc, 0x108);
         assert_eq!(cpu.stack.len(), 2);
@@ -507,7 +1346,7 @@ mod tests {
         let rom = rom_from(&[PUSH, 0xab, PUSH, 0x02, SUB, BRK]);
         let mut cpu = Cpu::new(&rom);
 
-        let pc = cpu.run(0x100, &mut AnyMachine {});
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
 
         assert_eq!(pc, 0x106);
         assert_eq!(cpu.stack.len(), 1);
@@ -519,7 +1358,7 @@ mod tests {
         let rom = rom_from(&[PUSH2, 0xab, 0xcd, PUSH2, 0x11, 0x11, SUB2, BRK]);
         let mut cpu = Cpu::new(&rom);
 
-        let pc = cpu.run(0x100, &mut AnyMachine {});
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
 
         assert_eq!(pc, 0x108);
         assert_eq!(cpu.stack.len(), 2);
@@ -531,7 +1370,7 @@ mod tests {
         let rom = rom_from(&[PUSH, 0x03, PUSH, 0x02, MUL, BRK]);
         let mut cpu = Cpu::new(&rom);
 
-        let pc = cpu.run(0x100, &mut AnyMachine {});
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
 
         assert_eq!(pc, 0x106);
         assert_eq!(cpu.stack.len(), 1);
@@ -543,7 +1382,7 @@ mod tests {
         let rom = rom_from(&[PUSH2, 0x11, 0x11, PUSH2, 0x00, 0x02, MUL2, BRK]);
         let mut cpu = Cpu::new(&rom);
 
-        let pc = cpu.run(0x100, &mut AnyMachine {});
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
 
         assert_eq!(pc, 0x108);
         assert_eq!(cpu.stack.len(), 2);
@@ -555,7 +1394,7 @@ mod tests {
         let rom = rom_from(&[PUSH, 0x07, PUSH, 0x02, DIV, BRK]);
         let mut cpu = Cpu::new(&rom);
 
-        let pc = cpu.run(0x100, &mut AnyMachine {});
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
 
         assert_eq!(pc, 0x106);
         assert_eq!(cpu.stack.len(), 1);
@@ -567,7 +1406,7 @@ mod tests {
         let rom = rom_from(&[PUSH2, 0x66, 0x66, PUSH2, 0x22, 0x22, DIV2, BRK]);
         let mut cpu = Cpu::new(&rom);
 
-        let pc = cpu.run(0x100, &mut AnyMachine {});
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
 
         assert_eq!(pc, 0x108);
         assert_eq!(cpu.stack.len(), 2);
@@ -579,7 +1418,7 @@ mod tests {
         let rom = rom_from(&[PUSH, 0x01, JMP, BRK, BRK]);
         let mut cpu = Cpu::new(&rom);
 
-        let pc = cpu.run(0x100, &mut AnyMachine {});
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
 
         assert_eq!(pc, 0x105);
         assert_eq!(cpu.stack.len(), 0);
@@ -590,7 +1429,7 @@ mod tests {
         let rom = rom_from(&[BRK, PUSH2, 0x01, 0x00, JMP2, BRK]);
         let mut cpu = Cpu::new(&rom);
 
-        let pc = cpu.run(0x101, &mut AnyMachine {});
+        let pc = cpu.run(0x101, &mut AnyMachine {}).unwrap();
 
         assert_eq!(pc, 0x101);
         assert_eq!(cpu.stack.len(), 0);
@@ -601,7 +1440,7 @@ mod tests {
         let rom = rom_from(&[PUSH2, 0x01, 0x01, JNZ, BRK, PUSH2, 0x00, 0x01, JNZ, BRK]);
         let mut cpu = Cpu::new(&rom);
 
-        let pc = cpu.run(0x100, &mut AnyMachine {});
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
 
         assert_eq!(pc, 0x10a);
         assert_eq!(cpu.stack.len(), 0);
@@ -614,7 +1453,7 @@ mod tests {
         ]);
         let mut cpu = Cpu::new(&rom);
 
-        let pc = cpu.run(0x100, &mut AnyMachine {});
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
 
         assert_eq!(pc, 0x10e);
         assert_eq!(cpu.stack.len(), 0);
@@ -626,7 +1465,7 @@ mod tests {
         let mut cpu = Cpu::new(&rom);
         cpu.ram[0x01] = 0xab;
 
-        let pc = cpu.run(0x100, &mut AnyMachine {});
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
 
         assert_eq!(pc, 0x104);
         assert_eq!(cpu.stack.len(), 1);
@@ -640,7 +1479,7 @@ mod tests {
         cpu.ram[0x01] = 0xab;
         cpu.ram[0x02] = 0xcd;
 
-        let pc = cpu.run(0x100, &mut AnyMachine {});
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
 
         assert_eq!(pc, 0x104);
         assert_eq!(cpu.stack.len(), 2);
@@ -652,7 +1491,7 @@ mod tests {
         let rom = rom_from(&[PUSH, 0xab, PUSH, 0x01, STZ, BRK]);
         let mut cpu = Cpu::new(&rom);
 
-        let pc = cpu.run(0x100, &mut AnyMachine {});
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
 
         assert_eq!(pc, 0x106);
         assert_eq!(cpu.stack.len(), 0);
@@ -664,7 +1503,7 @@ mod tests {
         let rom = rom_from(&[PUSH2, 0xab, 0xcd, PUSH, 0x01, STZ2, BRK]);
         let mut cpu = Cpu::new(&rom);
 
-        let pc = cpu.run(0x100, &mut AnyMachine {});
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
 
         assert_eq!(pc, 0x107);
         assert_eq!(cpu.stack.len(), 0);
@@ -676,7 +1515,7 @@ mod tests {
         let rom = rom_from(&[PUSH, 0xab, PUSH, 0xab, EQU, PUSH, 0x00, EQU, BRK]);
         let mut cpu = Cpu::new(&rom);
 
-        let pc = cpu.run(0x100, &mut AnyMachine {});
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
 
         assert_eq!(pc, 0x109);
         assert_eq!(cpu.stack.len(), 1);
@@ -688,10 +1527,1120 @@ mod tests {
         let rom = rom_from(&[PUSH2, 0xab, 0xcd, PUSH2, 0xab, 0xcd, EQU2, BRK]);
         let mut cpu = Cpu::new(&rom);
 
-        let pc = cpu.run(0x100, &mut AnyMachine {});
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x108);
+        assert_eq!(cpu.stack.len(), 1);
+        assert_eq!(cpu.stack.byte_at(0), 0x01);
+    }
+
+    #[test]
+    fn inc2_opcode() {
+        let rom = rom_from(&[PUSH2, 0x00, 0xff, INC2, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x105);
+        assert_eq!(cpu.stack.len(), 2);
+        assert_eq!(cpu.stack.short_at(0), 0x0100);
+    }
+
+    #[test]
+    fn pop_opcode() {
+        let rom = rom_from(&[PUSH, 0xab, PUSH, 0xcd, POP, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x106);
+        assert_eq!(cpu.stack.len(), 1);
+        assert_eq!(cpu.stack.byte_at(0), 0xab);
+    }
+
+    #[test]
+    fn pop2_opcode() {
+        let rom = rom_from(&[PUSH2, 0xab, 0xcd, POP2, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x105);
+        assert_eq!(cpu.stack.len(), 0);
+    }
+
+    #[test]
+    fn nip_opcode() {
+        let rom = rom_from(&[PUSH, 0xab, PUSH, 0xcd, NIP, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x106);
+        assert_eq!(cpu.stack.len(), 1);
+        assert_eq!(cpu.stack.byte_at(0), 0xcd);
+    }
+
+    #[test]
+    fn nip2_opcode() {
+        let rom = rom_from(&[PUSH2, 0xab, 0xcd, PUSH2, 0x12, 0x34, NIP2, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x108);
+        assert_eq!(cpu.stack.len(), 2);
+        assert_eq!(cpu.stack.short_at(0), 0x1234);
+    }
+
+    #[test]
+    fn swp_opcode() {
+        let rom = rom_from(&[PUSH, 0xab, PUSH, 0xcd, SWP, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x106);
+        assert_eq!(cpu.stack.len(), 2);
+        assert_eq!(cpu.stack.byte_at(0), 0xcd);
+        assert_eq!(cpu.stack.byte_at(1), 0xab);
+    }
+
+    #[test]
+    fn swp2_opcode() {
+        let rom = rom_from(&[PUSH2, 0xab, 0xcd, PUSH2, 0x12, 0x34, SWP2, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x108);
+        assert_eq!(cpu.stack.len(), 4);
+        assert_eq!(cpu.stack.short_at(0), 0x1234);
+        assert_eq!(cpu.stack.short_at(2), 0xabcd);
+    }
+
+    #[test]
+    fn rot_opcode() {
+        let rom = rom_from(&[PUSH, 0x01, PUSH, 0x02, PUSH, 0x03, ROT, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x108);
+        assert_eq!(cpu.stack.len(), 3);
+        assert_eq!(cpu.stack.byte_at(0), 0x02);
+        assert_eq!(cpu.stack.byte_at(1), 0x03);
+        assert_eq!(cpu.stack.byte_at(2), 0x01);
+    }
+
+    #[test]
+    fn rot2_opcode() {
+        let rom = rom_from(&[
+            PUSH2, 0x00, 0x01, PUSH2, 0x00, 0x02, PUSH2, 0x00, 0x03, ROT2, BRK,
+        ]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x10b);
+        assert_eq!(cpu.stack.len(), 6);
+        assert_eq!(cpu.stack.short_at(0), 0x0002);
+        assert_eq!(cpu.stack.short_at(2), 0x0003);
+        assert_eq!(cpu.stack.short_at(4), 0x0001);
+    }
+
+    #[test]
+    fn ovr_opcode() {
+        let rom = rom_from(&[PUSH, 0xab, PUSH, 0xcd, OVR, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x106);
+        assert_eq!(cpu.stack.len(), 3);
+        assert_eq!(cpu.stack.byte_at(0), 0xab);
+        assert_eq!(cpu.stack.byte_at(1), 0xcd);
+        assert_eq!(cpu.stack.byte_at(2), 0xab);
+    }
+
+    #[test]
+    fn ovr2_opcode() {
+        let rom = rom_from(&[PUSH2, 0xab, 0xcd, PUSH2, 0x12, 0x34, OVR2, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x108);
+        assert_eq!(cpu.stack.len(), 6);
+        assert_eq!(cpu.stack.short_at(0), 0xabcd);
+        assert_eq!(cpu.stack.short_at(2), 0x1234);
+        assert_eq!(cpu.stack.short_at(4), 0xabcd);
+    }
+
+    #[test]
+    fn neq_opcode() {
+        let rom = rom_from(&[PUSH, 0xab, PUSH, 0xab, NEQ, PUSH, 0x00, NEQ, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x109);
+        assert_eq!(cpu.stack.len(), 1);
+        assert_eq!(cpu.stack.byte_at(0), 0x00);
+    }
+
+    #[test]
+    fn neq2_opcode() {
+        let rom = rom_from(&[PUSH2, 0xab, 0xcd, PUSH2, 0xab, 0xce, NEQ2, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x108);
+        assert_eq!(cpu.stack.len(), 1);
+        assert_eq!(cpu.stack.byte_at(0), 0x01);
+    }
+
+    #[test]
+    fn gth_opcode() {
+        let rom = rom_from(&[PUSH, 0x02, PUSH, 0x01, GTH, PUSH, 0x01, GTH, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x109);
+        assert_eq!(cpu.stack.len(), 1);
+        assert_eq!(cpu.stack.byte_at(0), 0x00);
+    }
+
+    #[test]
+    fn gth2_opcode() {
+        let rom = rom_from(&[PUSH2, 0x01, 0x00, PUSH2, 0x00, 0xff, GTH2, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x108);
+        assert_eq!(cpu.stack.len(), 1);
+        assert_eq!(cpu.stack.byte_at(0), 0x01);
+    }
+
+    #[test]
+    fn lth_opcode() {
+        let rom = rom_from(&[PUSH, 0x01, PUSH, 0x02, LTH, PUSH, 0x01, LTH, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x109);
+        assert_eq!(cpu.stack.len(), 1);
+        assert_eq!(cpu.stack.byte_at(0), 0x00);
+    }
+
+    #[test]
+    fn lth2_opcode() {
+        let rom = rom_from(&[PUSH2, 0x00, 0xff, PUSH2, 0x01, 0x00, LTH2, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
 
         assert_eq!(pc, 0x108);
         assert_eq!(cpu.stack.len(), 1);
         assert_eq!(cpu.stack.byte_at(0), 0x01);
     }
+
+    #[test]
+    fn jmp_opcode_with_negative_offset() {
+        let rom = rom_from(&[PUSH, 0x02, JMP, BRK, BRK, PUSH, 0xfc, JMP]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x105);
+        assert_eq!(cpu.stack.len(), 0);
+    }
+
+    #[test]
+    fn jsr_opcode() {
+        let rom = rom_from(&[PUSH, 0x01, JSR, BRK, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x105);
+        assert_eq!(cpu.stack.len(), 0);
+        assert_eq!(cpu.ret_stack.len(), 2);
+        assert_eq!(cpu.ret_stack.short_at(0), 0x0103);
+    }
+
+    #[test]
+    fn jsr2_opcode() {
+        let rom = rom_from(&[PUSH2, 0x01, 0x05, JSR2, BRK, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x106);
+        assert_eq!(cpu.stack.len(), 0);
+        assert_eq!(cpu.ret_stack.len(), 2);
+        assert_eq!(cpu.ret_stack.short_at(0), 0x0104);
+    }
+
+    #[test]
+    fn sth_opcode() {
+        let rom = rom_from(&[PUSH, 0xab, STH, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x104);
+        assert_eq!(cpu.stack.len(), 0);
+        assert_eq!(cpu.ret_stack.len(), 1);
+        assert_eq!(cpu.ret_stack.byte_at(0), 0xab);
+    }
+
+    #[test]
+    fn sth2_opcode() {
+        let rom = rom_from(&[PUSH2, 0xab, 0xcd, STH2, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x105);
+        assert_eq!(cpu.stack.len(), 0);
+        assert_eq!(cpu.ret_stack.len(), 2);
+        assert_eq!(cpu.ret_stack.short_at(0), 0xabcd);
+    }
+
+    #[test]
+    fn ldr_opcode() {
+        let rom = rom_from(&[PUSH, 0x01, LDR, BRK, 0xab]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x104);
+        assert_eq!(cpu.stack.len(), 1);
+        assert_eq!(cpu.stack.byte_at(0), 0xab);
+    }
+
+    #[test]
+    fn ldr2_opcode() {
+        let rom = rom_from(&[PUSH, 0x01, LDR2, BRK, 0xab, 0xcd]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x104);
+        assert_eq!(cpu.stack.len(), 2);
+        assert_eq!(cpu.stack.short_at(0), 0xabcd);
+    }
+
+    #[test]
+    fn str_opcode() {
+        let rom = rom_from(&[PUSH, 0xab, PUSH, 0x01, STR, BRK, 0x00]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x106);
+        assert_eq!(cpu.stack.len(), 0);
+        assert_eq!(cpu.ram_peek_byte(0x106), 0xab);
+    }
+
+    #[test]
+    fn str2_opcode() {
+        let rom = rom_from(&[PUSH2, 0xab, 0xcd, PUSH, 0x01, STR2, BRK, 0x00, 0x00]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x107);
+        assert_eq!(cpu.stack.len(), 0);
+        assert_eq!(cpu.ram_peek_short(0x107), 0xabcd);
+    }
+
+    #[test]
+    fn lda_opcode() {
+        let rom = rom_from(&[PUSH2, 0x12, 0x34, LDA, BRK]);
+        let mut cpu = Cpu::new(&rom);
+        cpu.ram[0x1234] = 0xab;
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x105);
+        assert_eq!(cpu.stack.len(), 1);
+        assert_eq!(cpu.stack.byte_at(0), 0xab);
+    }
+
+    #[test]
+    fn lda2_opcode() {
+        let rom = rom_from(&[PUSH2, 0x12, 0x34, LDA2, BRK]);
+        let mut cpu = Cpu::new(&rom);
+        cpu.ram[0x1234] = 0xab;
+        cpu.ram[0x1235] = 0xcd;
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x105);
+        assert_eq!(cpu.stack.len(), 2);
+        assert_eq!(cpu.stack.short_at(0), 0xabcd);
+    }
+
+    #[test]
+    fn sta_opcode() {
+        let rom = rom_from(&[PUSH, 0xab, PUSH2, 0x12, 0x34, STA, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x107);
+        assert_eq!(cpu.stack.len(), 0);
+        assert_eq!(cpu.ram_peek_byte(0x1234), 0xab);
+    }
+
+    #[test]
+    fn sta2_opcode() {
+        let rom = rom_from(&[PUSH2, 0xab, 0xcd, PUSH2, 0x12, 0x34, STA2, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x108);
+        assert_eq!(cpu.stack.len(), 0);
+        assert_eq!(cpu.ram_peek_short(0x1234), 0xabcd);
+    }
+
+    #[test]
+    fn dei2_opcode() {
+        let rom = rom_from(&[PUSH, 0x10, DEI2, BRK]);
+        let mut cpu = Cpu::new(&rom);
+        cpu.devices[0x10] = 0xab;
+        cpu.devices[0x11] = 0xcd;
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x104);
+        assert_eq!(cpu.stack.len(), 2);
+        assert_eq!(cpu.stack.short_at(0), 0xabcd);
+    }
+
+    #[test]
+    fn and_opcode() {
+        let rom = rom_from(&[PUSH, 0xfc, PUSH, 0x3f, AND, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x106);
+        assert_eq!(cpu.stack.len(), 1);
+        assert_eq!(cpu.stack.byte_at(0), 0x3c);
+    }
+
+    #[test]
+    fn and2_opcode() {
+        let rom = rom_from(&[PUSH2, 0xff, 0xf0, PUSH2, 0x0f, 0xff, AND2, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x108);
+        assert_eq!(cpu.stack.len(), 2);
+        assert_eq!(cpu.stack.short_at(0), 0x0ff0);
+    }
+
+    #[test]
+    fn ora_opcode() {
+        let rom = rom_from(&[PUSH, 0xf0, PUSH, 0x0a, ORA, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x106);
+        assert_eq!(cpu.stack.len(), 1);
+        assert_eq!(cpu.stack.byte_at(0), 0xfa);
+    }
+
+    #[test]
+    fn ora2_opcode() {
+        let rom = rom_from(&[PUSH2, 0xab, 0x00, PUSH2, 0x00, 0xcd, ORA2, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x108);
+        assert_eq!(cpu.stack.len(), 2);
+        assert_eq!(cpu.stack.short_at(0), 0xabcd);
+    }
+
+    #[test]
+    fn eor_opcode() {
+        let rom = rom_from(&[PUSH, 0xff, PUSH, 0x0f, EOR, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x106);
+        assert_eq!(cpu.stack.len(), 1);
+        assert_eq!(cpu.stack.byte_at(0), 0xf0);
+    }
+
+    #[test]
+    fn eor2_opcode() {
+        let rom = rom_from(&[PUSH2, 0xff, 0xff, PUSH2, 0x0f, 0xf0, EOR2, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x108);
+        assert_eq!(cpu.stack.len(), 2);
+        assert_eq!(cpu.stack.short_at(0), 0xf00f);
+    }
+
+    #[test]
+    fn sft_opcode() {
+        let rom = rom_from(&[PUSH, 0x34, PUSH, 0x10, SFT, PUSH, 0x01, SFT, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x109);
+        assert_eq!(cpu.stack.len(), 1);
+        assert_eq!(cpu.stack.byte_at(0), 0x34);
+    }
+
+    #[test]
+    fn sft_opcode_shifts_out_all_bits() {
+        let rom = rom_from(&[PUSH, 0xff, PUSH, 0x09, SFT, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x106);
+        assert_eq!(cpu.stack.len(), 1);
+        assert_eq!(cpu.stack.byte_at(0), 0x00);
+    }
+
+    #[test]
+    fn sft2_opcode() {
+        let rom = rom_from(&[PUSH2, 0x00, 0xff, PUSH, 0x41, SFT2, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x107);
+        assert_eq!(cpu.stack.len(), 2);
+        assert_eq!(cpu.stack.short_at(0), 0x07f0);
+    }
+
+    #[test]
+    fn jci_opcode() {
+        let rom = rom_from(&[
+            PUSH, 0x01, JCI, 0x00, 0x01, BRK, PUSH, 0x00, JCI, 0x00, 0x01, BRK,
+        ]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x10c);
+        assert_eq!(cpu.stack.len(), 0);
+    }
+
+    #[test]
+    fn jmi_opcode() {
+        let rom = rom_from(&[JMI, 0x00, 0x01, BRK, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x105);
+        assert_eq!(cpu.stack.len(), 0);
+    }
+
+    #[test]
+    fn jmi_opcode_with_negative_offset() {
+        let rom = rom_from(&[BRK, JMI, 0xff, 0xfc]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x101, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x101);
+    }
+
+    #[test]
+    fn jsi_opcode() {
+        let rom = rom_from(&[JSI, 0x00, 0x01, BRK, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x105);
+        assert_eq!(cpu.stack.len(), 0);
+        assert_eq!(cpu.ret_stack.len(), 2);
+        assert_eq!(cpu.ret_stack.short_at(0), 0x0103);
+    }
+
+    #[test]
+    fn keep_mode_add_opcode() {
+        let rom = rom_from(&[PUSH, 0xab, PUSH, 0x02, ADD | FLAG_KEEP, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x106);
+        assert_eq!(cpu.stack.len(), 3);
+        assert_eq!(cpu.stack.byte_at(0), 0xab);
+        assert_eq!(cpu.stack.byte_at(1), 0x02);
+        assert_eq!(cpu.stack.byte_at(2), 0xad);
+    }
+
+    #[test]
+    fn keep_mode_add2_opcode() {
+        let rom = rom_from(&[PUSH2, 0xab, 0xcd, PUSH2, 0x11, 0x11, ADD2 | FLAG_KEEP, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x108);
+        assert_eq!(cpu.stack.len(), 6);
+        assert_eq!(cpu.stack.short_at(0), 0xabcd);
+        assert_eq!(cpu.stack.short_at(2), 0x1111);
+        assert_eq!(cpu.stack.short_at(4), 0xbcde);
+    }
+
+    #[test]
+    fn keep_mode_dup_opcode() {
+        let rom = rom_from(&[PUSH, 0xab, DUP | FLAG_KEEP, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x104);
+        assert_eq!(cpu.stack.len(), 3);
+        assert_eq!(cpu.stack.byte_at(0), 0xab);
+        assert_eq!(cpu.stack.byte_at(1), 0xab);
+        assert_eq!(cpu.stack.byte_at(2), 0xab);
+    }
+
+    #[test]
+    fn keep_mode_pop_opcode() {
+        let rom = rom_from(&[PUSH, 0xab, POP | FLAG_KEEP, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x104);
+        assert_eq!(cpu.stack.len(), 1);
+        assert_eq!(cpu.stack.byte_at(0), 0xab);
+    }
+
+    #[test]
+    fn keep_mode_swp_opcode() {
+        let rom = rom_from(&[PUSH, 0xab, PUSH, 0xcd, SWP | FLAG_KEEP, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x106);
+        assert_eq!(cpu.stack.len(), 4);
+        assert_eq!(cpu.stack.byte_at(0), 0xab);
+        assert_eq!(cpu.stack.byte_at(1), 0xcd);
+        assert_eq!(cpu.stack.byte_at(2), 0xcd);
+        assert_eq!(cpu.stack.byte_at(3), 0xab);
+    }
+
+    #[test]
+    fn keep_mode_equ_opcode() {
+        let rom = rom_from(&[PUSH, 0xab, PUSH, 0xab, EQU | FLAG_KEEP, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x106);
+        assert_eq!(cpu.stack.len(), 3);
+        assert_eq!(cpu.stack.byte_at(0), 0xab);
+        assert_eq!(cpu.stack.byte_at(1), 0xab);
+        assert_eq!(cpu.stack.byte_at(2), 0x01);
+    }
+
+    #[test]
+    fn keep_mode_lth2_opcode() {
+        let rom = rom_from(&[PUSH2, 0x00, 0xff, PUSH2, 0x01, 0x00, LTH2 | FLAG_KEEP, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x108);
+        assert_eq!(cpu.stack.len(), 5);
+        assert_eq!(cpu.stack.short_at(0), 0x00ff);
+        assert_eq!(cpu.stack.short_at(2), 0x0100);
+        assert_eq!(cpu.stack.byte_at(4), 0x01);
+    }
+
+    #[test]
+    fn keep_mode_jnz_opcode() {
+        let rom = rom_from(&[PUSH, 0x01, PUSH, 0x01, JNZ | FLAG_KEEP, BRK, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x107);
+        assert_eq!(cpu.stack.len(), 2);
+    }
+
+    #[test]
+    fn keep_mode_ldz_opcode() {
+        let rom = rom_from(&[PUSH, 0x01, LDZ | FLAG_KEEP, BRK]);
+        let mut cpu = Cpu::new(&rom);
+        cpu.ram[0x01] = 0xab;
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x104);
+        assert_eq!(cpu.stack.len(), 2);
+        assert_eq!(cpu.stack.byte_at(0), 0x01);
+        assert_eq!(cpu.stack.byte_at(1), 0xab);
+    }
+
+    #[test]
+    fn keep_mode_stz2_opcode() {
+        let rom = rom_from(&[PUSH2, 0xab, 0xcd, PUSH, 0x01, STZ2 | FLAG_KEEP, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x107);
+        assert_eq!(cpu.stack.len(), 3);
+        assert_eq!(cpu.stack.short_at(0), 0xabcd);
+        assert_eq!(cpu.stack.byte_at(2), 0x01);
+        assert_eq!(cpu.ram_peek_short(0x01), 0xabcd);
+    }
+
+    #[test]
+    fn keep_mode_lda2_opcode() {
+        let rom = rom_from(&[PUSH2, 0x12, 0x34, LDA2 | FLAG_KEEP, BRK]);
+        let mut cpu = Cpu::new(&rom);
+        cpu.ram[0x1234] = 0xab;
+        cpu.ram[0x1235] = 0xcd;
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x105);
+        assert_eq!(cpu.stack.len(), 4);
+        assert_eq!(cpu.stack.short_at(0), 0x1234);
+        assert_eq!(cpu.stack.short_at(2), 0xabcd);
+    }
+
+    #[test]
+    fn keep_mode_sta_opcode() {
+        let rom = rom_from(&[PUSH, 0xab, PUSH2, 0x12, 0x34, STA | FLAG_KEEP, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x107);
+        assert_eq!(cpu.stack.len(), 3);
+        assert_eq!(cpu.stack.byte_at(0), 0xab);
+        assert_eq!(cpu.stack.short_at(1), 0x1234);
+        assert_eq!(cpu.ram_peek_byte(0x1234), 0xab);
+    }
+
+    #[test]
+    fn keep_mode_dei_opcode() {
+        let rom = rom_from(&[PUSH, 0x10, DEI | FLAG_KEEP, BRK]);
+        let mut cpu = Cpu::new(&rom);
+        cpu.devices[0x10] = 0xab;
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x104);
+        assert_eq!(cpu.stack.len(), 2);
+        assert_eq!(cpu.stack.byte_at(0), 0x10);
+        assert_eq!(cpu.stack.byte_at(1), 0xab);
+    }
+
+    #[test]
+    fn keep_mode_deo2_opcode() {
+        let rom = rom_from(&[PUSH2, 0xab, 0xcd, PUSH, 0x00, DEO2 | FLAG_KEEP, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x107);
+        assert_eq!(cpu.stack.len(), 3);
+        assert_eq!(cpu.stack.short_at(0), 0xabcd);
+        assert_eq!(cpu.stack.byte_at(2), 0x00);
+        assert_eq!(cpu.devices[0x00], 0xab);
+        assert_eq!(cpu.devices[0x01], 0xcd);
+    }
+
+    #[test]
+    fn keep_mode_is_reset_after_each_opcode() {
+        let rom = rom_from(&[PUSH, 0x01, INC | FLAG_KEEP, POP, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x105);
+        assert_eq!(cpu.stack.len(), 1);
+        assert_eq!(cpu.stack.byte_at(0), 0x01);
+    }
+
+    #[test]
+    fn ret_mode_push_opcode() {
+        let rom = rom_from(&[PUSHR, 0xab, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x103);
+        assert_eq!(cpu.stack.len(), 0);
+        assert_eq!(cpu.ret_stack.len(), 1);
+        assert_eq!(cpu.ret_stack.byte_at(0), 0xab);
+    }
+
+    #[test]
+    fn ret_mode_push2_opcode() {
+        let rom = rom_from(&[PUSH2R, 0xab, 0xcd, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x104);
+        assert_eq!(cpu.stack.len(), 0);
+        assert_eq!(cpu.ret_stack.len(), 2);
+        assert_eq!(cpu.ret_stack.short_at(0), 0xabcd);
+    }
+
+    #[test]
+    fn ret_mode_inc_opcode() {
+        let rom = rom_from(&[PUSH, 0x01, PUSHR, 0xff, INC | FLAG_RET, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x106);
+        assert_eq!(cpu.stack.len(), 1);
+        assert_eq!(cpu.stack.byte_at(0), 0x01);
+        assert_eq!(cpu.ret_stack.len(), 1);
+        assert_eq!(cpu.ret_stack.byte_at(0), 0x00);
+    }
+
+    #[test]
+    fn ret_mode_add2_opcode() {
+        let rom = rom_from(&[PUSH2R, 0xab, 0xcd, PUSH2R, 0x11, 0x11, ADD2 | FLAG_RET, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x108);
+        assert_eq!(cpu.stack.len(), 0);
+        assert_eq!(cpu.ret_stack.len(), 2);
+        assert_eq!(cpu.ret_stack.short_at(0), 0xbcde);
+    }
+
+    #[test]
+    fn ret_mode_gth_opcode() {
+        let rom = rom_from(&[PUSHR, 0x02, PUSHR, 0x01, GTH | FLAG_RET, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x106);
+        assert_eq!(cpu.stack.len(), 0);
+        assert_eq!(cpu.ret_stack.len(), 1);
+        assert_eq!(cpu.ret_stack.byte_at(0), 0x01);
+    }
+
+    #[test]
+    fn ret_mode_keep_mode_add_opcode() {
+        let rom = rom_from(&[PUSHR, 0xab, PUSHR, 0x02, ADD | FLAG_RET | FLAG_KEEP, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x106);
+        assert_eq!(cpu.stack.len(), 0);
+        assert_eq!(cpu.ret_stack.len(), 3);
+        assert_eq!(cpu.ret_stack.byte_at(0), 0xab);
+        assert_eq!(cpu.ret_stack.byte_at(1), 0x02);
+        assert_eq!(cpu.ret_stack.byte_at(2), 0xad);
+    }
+
+    #[test]
+    fn ret_mode_sth_opcode() {
+        let rom = rom_from(&[PUSHR, 0xab, STH | FLAG_RET, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x104);
+        assert_eq!(cpu.ret_stack.len(), 0);
+        assert_eq!(cpu.stack.len(), 1);
+        assert_eq!(cpu.stack.byte_at(0), 0xab);
+    }
+
+    #[test]
+    fn keep_mode_sth2_opcode() {
+        let rom = rom_from(&[PUSH2, 0xab, 0xcd, STH2 | FLAG_KEEP, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x105);
+        assert_eq!(cpu.stack.len(), 2);
+        assert_eq!(cpu.stack.short_at(0), 0xabcd);
+        assert_eq!(cpu.ret_stack.len(), 2);
+        assert_eq!(cpu.ret_stack.short_at(0), 0xabcd);
+    }
+
+    #[test]
+    fn ret_mode_jmp2_opcode() {
+        let rom = rom_from(&[BRK, PUSH2R, 0x01, 0x00, JMP2 | FLAG_RET, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x101, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x101);
+        assert_eq!(cpu.stack.len(), 0);
+        assert_eq!(cpu.ret_stack.len(), 0);
+    }
+
+    #[test]
+    fn ret_mode_jsr_opcode() {
+        let rom = rom_from(&[PUSHR, 0x01, JSR | FLAG_RET, BRK, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x105);
+        assert_eq!(cpu.ret_stack.len(), 0);
+        assert_eq!(cpu.stack.len(), 2);
+        assert_eq!(cpu.stack.short_at(0), 0x0103);
+    }
+
+    #[test]
+    fn ret_mode_ldz_opcode() {
+        let rom = rom_from(&[PUSHR, 0x01, LDZ | FLAG_RET, BRK]);
+        let mut cpu = Cpu::new(&rom);
+        cpu.ram[0x01] = 0xab;
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x104);
+        assert_eq!(cpu.stack.len(), 0);
+        assert_eq!(cpu.ret_stack.len(), 1);
+        assert_eq!(cpu.ret_stack.byte_at(0), 0xab);
+    }
+
+    #[test]
+    fn ret_mode_deo_opcode() {
+        let rom = rom_from(&[PUSHR, 0xab, PUSHR, 0x02, DEO | FLAG_RET, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x106);
+        assert_eq!(cpu.ret_stack.len(), 0);
+        assert_eq!(cpu.devices[0x02], 0xab);
+    }
+
+    #[test]
+    fn calls_and_returns_from_subroutine() {
+        // main: call @double with 0x21 and then stop
+        // double: DUP ADD JMP2r
+        let rom = rom_from(&[
+            PUSH,
+            0x21,
+            PUSH2,
+            0x01,
+            0x07,
+            JSR2,
+            BRK,
+            DUP,
+            ADD,
+            JMP2 | FLAG_RET,
+        ]);
+        let mut cpu = Cpu::new(&rom);
+
+        let pc = cpu.run(0x100, &mut AnyMachine {}).unwrap();
+
+        assert_eq!(pc, 0x107);
+        assert_eq!(cpu.ret_stack.len(), 0);
+        assert_eq!(cpu.stack.len(), 1);
+        assert_eq!(cpu.stack.byte_at(0), 0x42);
+    }
+
+    #[test]
+    fn loads_fault_frame_into_the_working_stack() {
+        let rom = rom_from(&[PUSH, 0xab, PUSH, 0x00, DIV, BRK]);
+        let mut cpu = Cpu::new(&rom);
+        let trap = cpu.run(0x100, &mut AnyMachine {}).unwrap_err();
+
+        cpu.load_fault_frame(&trap);
+
+        assert_eq!(cpu.stack.len(), 4);
+        assert_eq!(cpu.stack.short_at(0), 0x0104);
+        assert_eq!(cpu.stack.byte_at(2), DIV);
+        assert_eq!(cpu.stack.byte_at(3), 0x03);
+    }
+
+    #[test]
+    fn loads_fault_frame_after_return_stack_overflow() {
+        let rom = rom_from(&[JSI, 0xff, 0xfd, BRK]); // calls itself forever
+        let mut cpu = Cpu::new(&rom);
+        let trap = cpu.run(0x100, &mut AnyMachine {}).unwrap_err();
+        assert_eq!(trap.fault, Fault::StackOverflow);
+
+        cpu.load_fault_frame(&trap);
+
+        assert_eq!(cpu.ret_stack.len(), 0);
+        assert_eq!(cpu.stack.len(), 4);
+        assert_eq!(cpu.stack.byte_at(2), JSI);
+        assert_eq!(cpu.stack.byte_at(3), 0x02);
+    }
+
+    #[test]
+    fn faults_on_stack_underflow() {
+        let rom = rom_from(&[PUSH, 0x01, ADD, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let res = cpu.run(0x100, &mut AnyMachine {});
+
+        assert_eq!(
+            res,
+            Err(Trap {
+                fault: Fault::StackUnderflow,
+                pc: 0x102,
+                opcode: ADD
+            })
+        );
+    }
+
+    #[test]
+    fn faults_on_stack_underflow_in_keep_mode() {
+        let rom = rom_from(&[PUSH, 0x01, POP2 | FLAG_KEEP, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let res = cpu.run(0x100, &mut AnyMachine {});
+
+        assert_eq!(
+            res,
+            Err(Trap {
+                fault: Fault::StackUnderflow,
+                pc: 0x102,
+                opcode: POP2 | FLAG_KEEP
+            })
+        );
+    }
+
+    #[test]
+    fn faults_on_return_stack_underflow() {
+        let rom = rom_from(&[JMP2 | FLAG_RET, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let res = cpu.run(0x100, &mut AnyMachine {});
+
+        assert_eq!(res.unwrap_err().fault, Fault::StackUnderflow);
+    }
+
+    #[test]
+    fn faults_on_stack_overflow() {
+        let rom = rom_from(&[PUSH, 0xab, DUP, PUSH, 0xfc, JMP]);
+        let mut cpu = Cpu::new(&rom);
+
+        let res = cpu.run(0x100, &mut AnyMachine {});
+
+        assert_eq!(
+            res,
+            Err(Trap {
+                fault: Fault::StackOverflow,
+                pc: 0x103,
+                opcode: PUSH
+            })
+        );
+        assert_eq!(cpu.stack.len(), 0xff);
+    }
+
+    #[test]
+    fn faults_on_division_by_zero() {
+        let rom = rom_from(&[PUSH, 0x07, PUSH, 0x00, DIV, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let res = cpu.run(0x100, &mut AnyMachine {});
+
+        assert_eq!(
+            res,
+            Err(Trap {
+                fault: Fault::DivisionByZero,
+                pc: 0x104,
+                opcode: DIV
+            })
+        );
+    }
+
+    #[test]
+    fn faults_on_division2_by_zero() {
+        let rom = rom_from(&[PUSH2, 0x66, 0x66, PUSH2, 0x00, 0x00, DIV2, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let res = cpu.run(0x100, &mut AnyMachine {});
+
+        assert_eq!(res.unwrap_err().fault, Fault::DivisionByZero);
+    }
+
+    #[test]
+    fn faults_raised_by_the_machine() {
+        struct NoDevicesMachine {}
+        impl Machine for NoDevicesMachine {
+            fn deo(&mut self, _: &mut Cpu, target: u8) -> Result<(), Fault> {
+                Err(Fault::UnknownDevice(target))
+            }
+            fn dei(&mut self, _: &mut Cpu, target: u8) -> Result<(), Fault> {
+                Err(Fault::UnknownDevice(target))
+            }
+        }
+
+        let rom = rom_from(&[PUSH, 0xab, PUSH, 0x42, DEO, BRK]);
+        let mut cpu = Cpu::new(&rom);
+
+        let res = cpu.run(0x100, &mut NoDevicesMachine {});
+
+        assert_eq!(
+            res,
+            Err(Trap {
+                fault: Fault::UnknownDevice(0x42),
+                pc: 0x104,
+                opcode: DEO
+            })
+        );
+    }
+    #[test]
+    fn dei_pushes_the_value_set_by_the_machine() {
+        struct CounterMachine {
+            reads: u8,
+        }
+        impl Machine for CounterMachine {
+            fn deo(&mut self, _: &mut Cpu, _: u8) -> Result<(), Fault> {
+                Ok(())
+            }
+            fn dei(&mut self, cpu: &mut Cpu, target: u8) -> Result<(), Fault> {
+                self.reads += 1;
+                cpu.devices[target as usize] = self.reads;
+                cpu.devices[target.wrapping_add(1) as usize] = 0xff;
+                Ok(())
+            }
+        }
+
+        let rom = rom_from(&[PUSH, 0x10, DEI, PUSH, 0x20, DEI2, BRK]);
+        let mut cpu = Cpu::new(&rom);
+        let mut machine = CounterMachine { reads: 0 };
+
+        cpu.run(0x100, &mut machine).unwrap();
+
+        assert_eq!(machine.reads, 2);
+        assert_eq!(cpu.stack.len(), 3);
+        assert_eq!(cpu.stack.byte_at(0), 0x01);
+        assert_eq!(cpu.stack.short_at(1), 0x02ff);
+    }
 }
@@ -1,18 +1,53 @@
+use core::fmt;
+
 pub const BRK: u8 = 0x00;
+pub const JCI: u8 = 0x20;
+pub const JMI: u8 = 0x40;
+pub const JSI: u8 = 0x60;
 pub const INC: u8 = 0x01;
+pub const INC2: u8 = 0x21;
+pub const POP: u8 = 0x02;
+pub const POP2: u8 = 0x22;
+pub const NIP: u8 = 0x03;
+pub const NIP2: u8 = 0x23;
+pub const SWP: u8 = 0x04;
+pub const SWP2: u8 = 0x24;
+pub const ROT: u8 = 0x05;
+pub const ROT2: u8 = 0x25;
 pub const DUP: u8 = 0x06;
 pub const DUP2: u8 = 0x26;
+pub const OVR: u8 = 0x07;
+pub const OVR2: u8 = 0x27;
 pub const EQU: u8 = 0x08;
 pub const EQU2: u8 = 0x28;
+pub const NEQ: u8 = 0x09;
+pub const NEQ2: u8 = 0x29;
+pub const GTH: u8 = 0x0a;
+pub const GTH2: u8 = 0x2a;
+pub const LTH: u8 = 0x0b;
+pub const LTH2: u8 = 0x2b;
 pub const JMP: u8 = 0x0c;
 pub const JMP2: u8 = 0x2c;
 pub const JNZ: u8 = 0x0d;
 pub const JNZ2: u8 = 0x2d;
+pub const JSR: u8 = 0x0e;
+pub const JSR2: u8 = 0x2e;
+pub const STH: u8 = 0x0f;
+pub const STH2: u8 = 0x2f;
 pub const LDZ: u8 = 0x10;
 pub const LDZ2: u8 = 0x30;
 pub const STZ: u8 = 0x11;
 pub const STZ2: u8 = 0x31;
+pub const LDR: u8 = 0x12;
+pub const LDR2: u8 = 0x32;
+pub const STR: u8 = 0x13;
+pub const STR2: u8 = 0x33;
+pub const LDA: u8 = 0x14;
+pub const LDA2: u8 = 0x34;
+pub const STA: u8 = 0x15;
+pub const STA2: u8 = 0x35;
 pub const DEI: u8 = 0x16;
+pub const DEI2: u8 = 0x36;
 pub const DEO: u8 = 0x17;
 pub const DEO2: u8 = 0x37;
 pub const ADD: u8 = 0x18;
@@ -23,8 +58,18 @@ pub const MUL: u8 = 0x1a;
 pub const MUL2: u8 = 0x3a;
 pub const DIV: u8 = 0x1b;
 pub const DIV2: u8 = 0x3b;
+pub const AND: u8 = 0x1c;
+pub const AND2: u8 = 0x3c;
+pub const ORA: u8 = 0x1d;
+pub const ORA2: u8 = 0x3d;
+pub const EOR: u8 = 0x1e;
+pub const EOR2: u8 = 0x3e;
+pub const SFT: u8 = 0x1f;
+pub const SFT2: u8 = 0x3f;
 pub const PUSH: u8 = 0x80;
 pub const PUSH2: u8 = 0xa0;
+pub const PUSHR: u8 = 0xc0;
+pub const PUSH2R: u8 = 0xe0;
 
 pub const FLAG_SHORT: u8 = 0b0010_0000;
 pub const FLAG_RET: u8 = 0b0100_0000;
@@ -41,3 +86,88 @@ pub fn ret_mode(opcode: u8) -> bool {
 pub fn keep_mode(opcode: u8) -> bool {
     (opcode & FLAG_KEEP) == FLAG_KEEP
 }
+
+const NAMES: [&str; 0x20] = [
+    "BRK", "INC", "POP", "NIP", "SWP", "ROT", "DUP", "OVR", "EQU", "NEQ", "GTH", "LTH", "JMP",
+    "JCN", "JSR", "STH", "LDZ", "STZ", "LDR", "STR", "LDA", "STA", "DEI", "DEO", "ADD", "SUB",
+    "MUL", "DIV", "AND", "ORA", "EOR", "SFT",
+];
+
+/// The Tal mnemonic of an opcode, with its mode suffixes (e.g. `ADD2kr`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mnemonic(pub u8);
+
+impl Mnemonic {
+    /// Returns the name of the opcode, without mode suffixes
+    pub fn name(&self) -> &'static str {
+        match self.0 {
+            JCI => "JCI",
+            JMI => "JMI",
+            JSI => "JSI",
+            PUSH | PUSH2 | PUSHR | PUSH2R => "LIT",
+            op => NAMES[(op & 0x1f) as usize],
+        }
+    }
+}
+
+impl fmt::Display for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = self.0;
+        let name = self.name();
+        let has_modes = (op & 0x1f) != 0x00 || keep_mode(op);
+
+        // write into a buffer first, so padding and alignment flags work
+        let mut buffer = [0_u8; 6];
+        buffer[..3].copy_from_slice(name.as_bytes());
+        let mut len = 3;
+        let suffixes = [
+            (short_mode(op), b'2'),
+            (keep_mode(op) && (op & 0x1f) != 0x00, b'k'),
+            (ret_mode(op), b'r'),
+        ];
+        for (is_set, suffix) in suffixes {
+            if has_modes && is_set {
+                buffer[len] = suffix;
+                len += 1;
+            }
+        }
+
+        f.pad(core::str::from_utf8(&buffer[..len]).unwrap_or(name))
+    }
+}
+
+/// Returns the mnemonic of the given opcode
+pub fn mnemonic(opcode: u8) -> Mnemonic {
+    Mnemonic(opcode)
+}
+
+/// Returns the opcode of a Tal mnemonic with optional mode suffixes (e.g.
+/// `ADD2kr`), or `None` if it isn't a valid one
+pub fn from_mnemonic(mnemonic: &str) -> Option<u8> {
+    let name = mnemonic.get(..3)?;
+    let suffixes = &mnemonic[3..];
+
+    let mut opcode = match name {
+        "BRK" | "JCI" | "JMI" | "JSI" if !suffixes.is_empty() => return None,
+        "JCI" => JCI,
+        "JMI" => JMI,
+        "JSI" => JSI,
+        "LIT" => PUSH,
+        _ => NAMES.iter().position(|&x| x == name)? as u8,
+    };
+
+    for suffix in suffixes.bytes() {
+        let flag = match suffix {
+            b'2' => FLAG_SHORT,
+            b'k' => FLAG_KEEP,
+            b'r' => FLAG_RET,
+            _ => return None,
+        };
+        if (opcode & flag) == flag {
+            return None;
+        }
+        opcode |= flag;
+    }
+
+    Some(opcode)
+}
@@ -1,16 +1,27 @@
 use core::fmt;
 
+use crate::Fault;
+
 #[derive(Debug)]
 pub struct Stack {
     data: [u8; 0x100],
     index: u8,
+    /// Whether pops should leave the stack untouched (keep mode)
+    keep: bool,
+    /// Index of the next byte to be popped in keep mode
+    cursor: u8,
 }
 
 impl Stack {
+    /// Max. number of bytes the stack can hold
+    pub const CAPACITY: usize = 0xff;
+
     pub fn new() -> Self {
         Self {
             data: [0_u8; 0x100],
             index: u8::MAX,
+            keep: false,
+            cursor: u8::MAX,
         }
     }
 
@@ -18,31 +29,68 @@ impl Stack {
         self.index.wrapping_add(1) as usize
     }
 
-    pub fn push_byte(&mut self, x: u8) {
+    /// Returns the bytes in the stack, from bottom to top
+    pub fn as_slice(&self) -> &[u8] {
+        &self.data[..self.len()]
+    }
+
+    /// Removes all the bytes from the stack
+    pub fn clear(&mut self) {
+        self.index = u8::MAX;
+        self.cursor = u8::MAX;
+    }
+
+    /// Enables or disables keep mode. While enabled, pops read the
+    /// stack from the top downwards without consuming any bytes.
+    pub fn set_keep_mode(&mut self, keep: bool) {
+        self.keep = keep;
+        self.cursor = self.index;
+    }
+
+    pub fn push_byte(&mut self, x: u8) -> Result<(), Fault> {
+        if self.len() == Self::CAPACITY {
+            return Err(Fault::StackOverflow);
+        }
+
         self.index = self.index.wrapping_add(1);
         self.data[self.index as usize] = x;
+        Ok(())
     }
 
-    pub fn push_short(&mut self, x: u16) {
+    pub fn push_short(&mut self, x: u16) -> Result<(), Fault> {
         let [hi, lo] = x.to_be_bytes();
-        self.push_byte(hi);
-        self.push_byte(lo);
+        self.push_byte(hi)?;
+        self.push_byte(lo)
     }
 
-    pub fn pop_byte(&mut self) -> u8 {
+    pub fn pop_byte(&mut self) -> Result<u8, Fault> {
+        if self.keep {
+            if self.cursor == u8::MAX {
+                return Err(Fault::StackUnderflow);
+            }
+
+            let res = self.data[self.cursor as usize];
+            self.cursor = self.cursor.wrapping_sub(1);
+            return Ok(res);
+        }
+
+        if self.len() == 0 {
+            return Err(Fault::StackUnderflow);
+        }
+
         let res = self.data[self.index as usize];
         self.index = self.index.wrapping_sub(1);
-        res
+        Ok(res)
     }
 
-    pub fn pop_short(&mut self) -> u16 {
-        let lo = self.pop_byte();
-        let hi = self.pop_byte();
-        u16::from_be_bytes([hi, lo])
+    pub fn pop_short(&mut self) -> Result<u16, Fault> {
+        let lo = self.pop_byte()?;
+        let hi = self.pop_byte()?;
+        Ok(u16::from_be_bytes([hi, lo]))
     }
 
     pub fn byte_at(&self, i: u8) -> u8 {
-        return self.data[i as usize];
+        self.data[i as usize]
     }
 
     #[cfg(test)]
@@ -0,0 +1,276 @@
+use core::fmt;
+
+use crate::opcodes::mnemonic;
+use crate::Access;
+
+/// How many bytes from the top of a stack are kept in a trace entry
+pub const SNAPSHOT_LEN: usize = 8;
+
+/// The topmost bytes of a stack at a given time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StackSnapshot {
+    /// Number of bytes in the whole stack
+    pub len: u8,
+    top: [u8; SNAPSHOT_LEN],
+}
+
+impl StackSnapshot {
+    pub fn new(stack: &[u8]) -> Self {
+        let bytes = &stack[stack.len().saturating_sub(SNAPSHOT_LEN)..];
+        let mut top = [0x00; SNAPSHOT_LEN];
+        top[..bytes.len()].copy_from_slice(bytes);
+
+        Self {
+            len: stack.len() as u8,
+            top,
+        }
+    }
+
+    /// Returns the topmost bytes of the stack, from bottom to top
+    pub fn top(&self) -> &[u8] {
+        &self.top[..(self.len as usize).min(SNAPSHOT_LEN)]
+    }
+}
+
+impl fmt::Display for StackSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[")?;
+        if self.len as usize > SNAPSHOT_LEN {
+            write!(f, ".. ")?;
+        }
+        for (i, byte) in self.top().iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+        write!(f, "]")
+    }
+}
+
+/// The record of a single executed instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TraceEntry {
+    pub pc: u16,
+    pub opcode: u8,
+    pub stack_before: StackSnapshot,
+    pub stack_after: StackSnapshot,
+    pub ret_stack_before: StackSnapshot,
+    pub ret_stack_after: StackSnapshot,
+    /// RAM or device access made by the instruction
+    pub access: Option<Access>,
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04x}  {:<6}  WRK: {} -> {}  RET: {} -> {}",
+            self.pc,
+            mnemonic(self.opcode),
+            self.stack_before,
+            self.stack_after,
+            self.ret_stack_before,
+            self.ret_stack_after
+        )?;
+
+        let width = |short: bool| if short { 4 } else { 2 };
+        match self.access {
+            Some(Access::RamRead { addr, value, short }) => {
+                write!(f, "  RAM {:04x} -> {:0w$x}", addr, value, w = width(short))
+            }
+            Some(Access::RamWrite { addr, value, short }) => {
+                write!(f, "  RAM {:04x} <- {:0w$x}", addr, value, w = width(short))
+            }
+            Some(Access::PortRead { port, value, short }) => {
+                write!(f, "  DEV {:02x} -> {:0w$x}", port, value, w = width(short))
+            }
+            Some(Access::PortWrite { port, value, short }) => {
+                write!(f, "  DEV {:02x} <- {:0w$x}", port, value, w = width(short))
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// The trait to implement for receivers of execution traces.
+pub trait TraceSink {
+    fn record(&mut self, entry: &TraceEntry);
+}
+
+/// A trace sink that writes each entry as a line of text.
+#[derive(Debug)]
+pub struct TraceWriter<W: fmt::Write>(pub W);
+
+impl<W: fmt::Write> TraceSink for TraceWriter<W> {
+    fn record(&mut self, entry: &TraceEntry) {
+        // tracing is best-effort: a failing writer shouldn't stop the CPU
+        let _ = writeln!(self.0, "{}", entry);
+    }
+}
+
+/// A trace sink that keeps the last `N` entries.
+#[derive(Debug, Clone)]
+pub struct TraceBuffer<const N: usize> {
+    entries: [TraceEntry; N],
+    /// Index of the slot the next entry will be written to
+    next: usize,
+    len: usize,
+}
+
+impl<const N: usize> Default for TraceBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> TraceBuffer<N> {
+    pub fn new() -> Self {
+        Self {
+            entries: [TraceEntry::default(); N],
+            next: 0,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.next = 0;
+        self.len = 0;
+    }
+
+    /// Returns the recorded entries, from oldest to newest
+    pub fn iter(&self) -> impl Iterator<Item = &TraceEntry> {
+        let start = (self.next + N - self.len) % N.max(1);
+        (0..self.len).map(move |i| &self.entries[(start + i) % N])
+    }
+}
+
+impl<const N: usize> TraceSink for TraceBuffer<N> {
+    fn record(&mut self, entry: &TraceEntry) {
+        if N == 0 {
+            return;
+        }
+
+        self.entries[self.next] = *entry;
+        self.next = (self.next + 1) % N;
+        self.len = (self.len + 1).min(N);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opcodes::*;
+    use crate::{Cpu, Fault, Machine, RunStatus};
+
+    struct AnyMachine {}
+    impl Machine for AnyMachine {
+        fn deo(&mut self, _: &mut Cpu, _: u8) -> Result<(), Fault> {
+            Ok(())
+        }
+        fn dei(&mut self, _: &mut Cpu, _: u8) -> Result<(), Fault> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn records_every_instruction() {
+        let mut cpu = Cpu::new(&[PUSH, 0x01, INC, BRK]);
+        let mut buffer = TraceBuffer::<8>::new();
+
+        let pc = cpu.run_traced(0x100, &mut AnyMachine {}, &mut buffer);
+
+        assert_eq!(pc, Ok(0x104));
+        let entries: Vec<_> = buffer.iter().collect();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].pc, 0x100);
+        assert_eq!(entries[0].opcode, PUSH);
+        assert_eq!(entries[1].pc, 0x102);
+        assert_eq!(entries[1].stack_before.top(), &[0x01]);
+        assert_eq!(entries[1].stack_after.top(), &[0x02]);
+        assert_eq!(entries[2].opcode, BRK);
+    }
+
+    #[test]
+    fn records_device_io() {
+        let mut cpu = Cpu::new(&[PUSH2, 0xab, 0xcd, PUSH, 0x18, DEO2, BRK]);
+        let mut buffer = TraceBuffer::<8>::new();
+
+        cpu.run_traced(0x100, &mut AnyMachine {}, &mut buffer)
+            .unwrap();
+
+        let entry = buffer.iter().nth(2).unwrap();
+        assert_eq!(
+            entry.access,
+            Some(Access::PortWrite {
+                port: 0x18,
+                value: 0xabcd,
+                short: true
+            })
+        );
+    }
+
+    #[test]
+    fn records_faulting_instruction() {
+        let mut cpu = Cpu::new(&[PUSH, 0x01, ADD, BRK]);
+        let mut buffer = TraceBuffer::<8>::new();
+
+        let status = cpu.run_for_traced(0x100, &mut AnyMachine {}, 10, &mut buffer);
+
+        assert!(matches!(status, RunStatus::Fault(_)));
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer.iter().last().unwrap().opcode, ADD);
+    }
+
+    #[test]
+    fn ring_buffer_keeps_last_entries() {
+        let mut cpu = Cpu::new(&[PUSH, 0x01, INC, INC, INC, BRK]);
+        let mut buffer = TraceBuffer::<2>::new();
+
+        cpu.run_traced(0x100, &mut AnyMachine {}, &mut buffer)
+            .unwrap();
+
+        let pcs: Vec<_> = buffer.iter().map(|entry| entry.pc).collect();
+        assert_eq!(pcs, vec![0x104, 0x105]);
+    }
+
+    #[test]
+    fn writes_entries_as_text() {
+        let mut cpu = Cpu::new(&[PUSH2, 0xab, 0xcd, PUSH, 0x18, DEO2 | FLAG_KEEP, BRK]);
+        let mut writer = TraceWriter(String::new());
+
+        cpu.run_traced(0x100, &mut AnyMachine {}, &mut writer)
+            .unwrap();
+
+        let lines: Vec<_> = writer.0.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "0100  LIT2    WRK: [] -> [ab cd]  RET: [] -> []",
+                "0103  LIT     WRK: [ab cd] -> [ab cd 18]  RET: [] -> []",
+                "0105  DEO2k   WRK: [ab cd 18] -> [ab cd 18]  RET: [] -> []  DEV 18 <- abcd",
+                "0106  BRK     WRK: [ab cd 18] -> [ab cd 18]  RET: [] -> []",
+            ]
+        );
+    }
+
+    #[test]
+    fn snapshots_keep_the_top_of_the_stack() {
+        let snapshot = StackSnapshot::new(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+
+        assert_eq!(snapshot.len, 10);
+        assert_eq!(snapshot.top(), &[2, 3, 4, 5, 6, 7, 8, 9]);
+        assert_eq!(
+            format!("{}", snapshot),
+            "[.. 02 03 04 05 06 07 08 09]".to_string()
+        );
+    }
+}
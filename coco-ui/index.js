@@ -1,13 +1,10 @@
-import initWasm, { runRom } from "./vendor/coco_ui.js";
+import initWasm, { disassemble, runRom } from "./vendor/coco_ui.js";
 
 async function handleFile(file) {
   const buffer = await file.arrayBuffer();
   const rom = new Uint8Array(buffer);
 
-  const bytecode = Array.from(rom)
-    .map((x) => x.toString(16).padStart(2, "0"))
-    .join(" ");
-  document.querySelector("#coco-bytecode").innerHTML = bytecode;
+  document.querySelector("#coco-bytecode").textContent = disassemble(rom);
 
   const output = runRom(rom);
   if (output.debug) {
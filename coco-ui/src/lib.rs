@@ -1,9 +1,12 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 use wasm_bindgen::prelude::*;
 
-use coco_core::Cpu;
-use coco_vm::{VideoBuffer, Vm, SCREEN_HEIGHT, SCREEN_WIDTH, VIDEO_BUFFER_LEN};
+use coco_core::{disasm, Cpu, Trap};
+use coco_vm::{
+    yearday, Button, Clock, DateTime, DeviceOutput, MemoryStorage, MouseButton, VideoBuffer, Vm,
+    MAX_CYCLES_PER_VECTOR, SAMPLE_RATE, SCREEN_HEIGHT, SCREEN_WIDTH, THEME, VIDEO_BUFFER_LEN,
+};
 
 #[wasm_bindgen(getter_with_clone)]
 #[derive(Debug)]
@@ -15,34 +18,52 @@ pub type Result<T> = core::result::Result<T, JsValue>;
 
 pub type DisplayBuffer = [u8; VIDEO_BUFFER_LEN * 4];
 pub type DeviceBuffer = VideoBuffer;
-pub type RGB = (u8, u8, u8);
-
-const THEME: [RGB; 0x10] = [
-    (0x00, 0x00, 0x00),
-    (0x1D, 0x2B, 0x53),
-    (0x7E, 0x25, 0x53),
-    (0x00, 0x87, 0x51),
-    (0xAB, 0x52, 0x36),
-    (0x5F, 0x57, 0x4F),
-    (0xC2, 0xC3, 0xC7),
-    (0xFF, 0xF1, 0xE8),
-    (0xFF, 0x00, 0x4D),
-    (0xFF, 0xA3, 0x00),
-    (0xFF, 0xEC, 0x27),
-    (0x00, 0xE4, 0x36),
-    (0x29, 0xAD, 0xFF),
-    (0x83, 0x76, 0x9C),
-    (0xFF, 0x77, 0xA8),
-    (0xFF, 0xCC, 0xAA),
-];
+
+/// Number of audio frames the VM renders for each video frame
+const AUDIO_FRAMES_PER_VIDEO_FRAME: usize = SAMPLE_RATE as usize / 60;
+
+/// How far ahead of the audio clock sound gets scheduled, in seconds
+const AUDIO_LATENCY: f64 = 0.05;
+
+thread_local! {
+    /// Files of the File device. They're kept in memory for as long as the
+    /// page is open, so ROMs can share them.
+    static STORAGE: Rc<RefCell<MemoryStorage>> = Rc::new(RefCell::new(MemoryStorage::new()));
+}
 
 #[wasm_bindgen(js_name=runRom)]
 pub fn run_rom(rom: &[u8]) -> Result<Output> {
-    let cpu = Rc::new(RefCell::new(Cpu::new(&rom)));
-    let vm = Rc::new(RefCell::new(Vm::new()));
+    let cpu = Rc::new(RefCell::new(Cpu::new(rom)));
+    let storage = STORAGE.with(Rc::clone);
+    let vm = Rc::new(RefCell::new(
+        Vm::new()
+            .with_storage(storage)
+            .with_clock(BrowserClock)
+            // std has no source of entropy in the browser
+            .with_seed((js_sys::Math::random() * 65_536.0) as u16),
+    ));
 
     // call reset vector
-    let output = vm.borrow_mut().on_reset(&mut cpu.borrow_mut());
+    let output = vm
+        .borrow_mut()
+        .on_reset(&mut cpu.borrow_mut())
+        .map_err(|trap| JsValue::from(trap.to_string()))?;
+    if output.is_paused {
+        return Err(JsValue::from(format!(
+            "Reset vector paused at {:04x} after {} cycles",
+            cpu.borrow().pc(),
+            MAX_CYCLES_PER_VECTOR
+        )));
+    }
+    log_console_output(&output);
+
+    // buttons held down in the keyboard, merged with the gamepads' every frame
+    let keyboard_buttons = Rc::new(Cell::new(0x00_u8));
+    listen_to_keyboard(vm.clone(), cpu.clone(), keyboard_buttons.clone());
+    listen_to_pointer(vm.clone(), cpu.clone());
+
+    let mut speaker = Speaker::new();
+    let mut audio_buffer = [0_i16; AUDIO_FRAMES_PER_VIDEO_FRAME * 2];
 
     // setup requestAnimationFrame handler
     let f = Rc::new(RefCell::new(None));
@@ -53,11 +74,56 @@ pub fn run_rom(rom: &[u8]) -> Result<Output> {
     render(&mut vm.borrow_mut(), &ctx, &mut canvas_buffer);
 
     *g.borrow_mut() = Some(Closure::new(move || {
-        let on_video_output = vm.borrow_mut().on_video(&mut cpu.borrow_mut());
-        if on_video_output.sys_stdout.len() > 0 {
+        let buttons = keyboard_buttons.get() | gamepad_buttons();
+        let pressed = vm.borrow_mut().on_buttons(&mut cpu.borrow_mut(), buttons);
+        let has_faulted = pressed.is_err();
+        log_vector_output("Controller", pressed, &cpu.borrow());
+        if has_faulted {
+            // stop the animation loop, since the ROM can't go on
+            return;
+        }
+
+        let on_video_output = match vm.borrow_mut().on_video(&mut cpu.borrow_mut()) {
+            Ok(output) => output,
+            Err(trap) => {
+                // stop the animation loop, since the ROM can't go on
+                web_sys::console::error_1(&JsValue::from(trap.to_string()));
+                return;
+            }
+        };
+        log_console_output(&on_video_output);
+        if !on_video_output.sys_stdout.is_empty() {
             web_sys::console::log_1(&JsValue::from(on_video_output.sys_stdout));
         }
         render(&mut vm.borrow_mut(), &ctx, &mut canvas_buffer);
+
+        if on_video_output.shall_halt {
+            // the ROM asked to stop, from this vector or an earlier one
+            web_sys::console::log_1(&JsValue::from("ROM halted"));
+            return;
+        }
+        if on_video_output.is_paused {
+            // stop the animation loop, so the runaway vector doesn't hang the page
+            web_sys::console::warn_1(&JsValue::from(format!(
+                "Video vector paused at {:04x} after {} cycles",
+                cpu.borrow().pc(),
+                MAX_CYCLES_PER_VECTOR
+            )));
+            return;
+        }
+
+        let played = vm
+            .borrow_mut()
+            .on_audio(&mut cpu.borrow_mut(), &mut audio_buffer);
+        let has_faulted = played.is_err();
+        log_vector_output("Audio", played, &cpu.borrow());
+        if has_faulted {
+            return;
+        }
+        if let Some(speaker) = &mut speaker {
+            speaker.play(&audio_buffer);
+        }
+
         request_animation_frame(f.borrow().as_ref().unwrap())
     }));
 
@@ -68,6 +134,343 @@ pub fn run_rom(rom: &[u8]) -> Result<Output> {
     })
 }
 
+/// The local time of the browser
+#[derive(Debug)]
+struct BrowserClock;
+
+impl Clock for BrowserClock {
+    fn now(&mut self) -> DateTime {
+        let now = js_sys::Date::new_0();
+        let year = now.get_full_year();
+        let month = now.get_month();
+        let day = now.get_date();
+
+        // the offset is smaller during daylight saving time
+        let january = js_sys::Date::new_with_year_month_day(year, 0, 1);
+        let july = js_sys::Date::new_with_year_month_day(year, 6, 1);
+        let standard_offset = january
+            .get_timezone_offset()
+            .max(july.get_timezone_offset());
+
+        DateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: now.get_hours() as u8,
+            minute: now.get_minutes() as u8,
+            second: now.get_seconds() as u8,
+            weekday: now.get_day() as u8,
+            yearday: yearday(year as u16, month as u8, day as u8),
+            is_dst: now.get_timezone_offset() < standard_offset,
+        }
+    }
+}
+
+/// Plays the PCM output of the VM through Web Audio, one buffer per frame
+struct Speaker {
+    ctx: web_sys::AudioContext,
+    /// Time at which the next buffer starts, in the audio clock
+    next_time: f64,
+}
+
+impl Speaker {
+    fn new() -> Option<Self> {
+        let ctx = match web_sys::AudioContext::new() {
+            Ok(ctx) => ctx,
+            Err(err) => {
+                web_sys::console::warn_2(&JsValue::from("Audio is not available:"), &err);
+                return None;
+            }
+        };
+
+        // browsers only allow audio after the user interacts with the page
+        let resume_ctx = ctx.clone();
+        let on_interaction = Closure::<dyn FnMut()>::new(move || {
+            let _ = resume_ctx.resume();
+        });
+        for name in ["keydown", "pointerdown"] {
+            window()
+                .add_event_listener_with_callback(name, on_interaction.as_ref().unchecked_ref())
+                .unwrap_or_else(|_| panic!("Cannot register `{}` handler", name));
+        }
+        on_interaction.forget();
+
+        Some(Self {
+            ctx,
+            next_time: 0.0,
+        })
+    }
+
+    /// Schedules interleaved stereo frames to play right after the previous
+    /// ones. Frames are dropped while the audio clock is suspended or too far
+    /// behind the VM.
+    fn play(&mut self, pcm: &[i16]) {
+        if self.ctx.state() != web_sys::AudioContextState::Running {
+            return;
+        }
+
+        let now = self.ctx.current_time();
+        if self.next_time < now {
+            self.next_time = now + AUDIO_LATENCY;
+        } else if self.next_time > now + AUDIO_LATENCY * 4.0 {
+            return;
+        }
+
+        let frames = pcm.len() / 2;
+        let Ok(buffer) = self.ctx.create_buffer(2, frames as u32, SAMPLE_RATE as f32) else {
+            return;
+        };
+        for side in 0..2 {
+            let samples: Vec<f32> = pcm
+                .iter()
+                .skip(side)
+                .step_by(2)
+                .map(|&x| x as f32 / i16::MAX as f32)
+                .collect();
+            let _ = buffer.copy_to_channel(&samples, side as i32);
+        }
+
+        let Ok(source) = self.ctx.create_buffer_source() else {
+            return;
+        };
+        source.set_buffer(Some(&buffer));
+        if source
+            .connect_with_audio_node(&self.ctx.destination())
+            .is_ok()
+        {
+            let _ = source.start_with_when(self.next_time);
+        }
+        self.next_time += frames as f64 / SAMPLE_RATE as f64;
+    }
+}
+
+/// Registers `keydown` and `keyup` handlers that keep track of the buttons
+/// mapped to the keyboard, and send typed characters to the controller
+fn listen_to_keyboard(vm: Rc<RefCell<Vm>>, cpu: Rc<RefCell<Cpu>>, buttons: Rc<Cell<u8>>) {
+    let pressed = buttons.clone();
+    let on_keydown = Closure::<dyn FnMut(_)>::new(move |event: web_sys::KeyboardEvent| {
+        if let Some(button) = button_for_key(&event.key()) {
+            event.prevent_default();
+            pressed.set(pressed.get() | button as u8);
+        } else if let Some(key) = ascii_for_key(&event.key()) {
+            event.prevent_default();
+            let typed = vm.borrow_mut().on_key(&mut cpu.borrow_mut(), key);
+            log_vector_output("Controller", typed, &cpu.borrow());
+        }
+    });
+
+    let on_keyup = Closure::<dyn FnMut(_)>::new(move |event: web_sys::KeyboardEvent| {
+        if let Some(button) = button_for_key(&event.key()) {
+            event.prevent_default();
+            buttons.set(buttons.get() & !(button as u8));
+        }
+    });
+
+    window()
+        .add_event_listener_with_callback("keydown", on_keydown.as_ref().unchecked_ref())
+        .expect("Cannot register `keydown` handler");
+    window()
+        .add_event_listener_with_callback("keyup", on_keyup.as_ref().unchecked_ref())
+        .expect("Cannot register `keyup` handler");
+
+    // the handlers live as long as the page
+    on_keydown.forget();
+    on_keyup.forget();
+}
+
+/// Registers pointer handlers on the canvas that send the pointer position
+/// (in screen pixels), buttons and scroll to the mouse
+fn listen_to_pointer(vm: Rc<RefCell<Vm>>, cpu: Rc<RefCell<Cpu>>) {
+    let canvas = canvas();
+
+    let pointer_vm = vm.clone();
+    let pointer_cpu = cpu.clone();
+    let pointer_canvas = canvas.clone();
+    let on_pointer = Closure::<dyn FnMut(_)>::new(move |event: web_sys::PointerEvent| {
+        if event.type_() == "pointerdown" {
+            // keep getting events if the pointer leaves the canvas while pressed
+            let _ = pointer_canvas.set_pointer_capture(event.pointer_id());
+        }
+        let (x, y) = screen_position(&pointer_canvas, event.client_x(), event.client_y());
+        let buttons = mouse_buttons(event.buttons());
+
+        let mut vm = pointer_vm.borrow_mut();
+        let mut cpu = pointer_cpu.borrow_mut();
+        let moved = vm.on_mouse_move(&mut cpu, x, y);
+        log_vector_output("Mouse", moved, &cpu);
+        let pressed = vm.on_mouse_buttons(&mut cpu, buttons);
+        log_vector_output("Mouse", pressed, &cpu);
+    });
+
+    let on_wheel = Closure::<dyn FnMut(_)>::new(move |event: web_sys::WheelEvent| {
+        event.prevent_default();
+        let x = event.delta_x().signum() as i16;
+        let y = event.delta_y().signum() as i16;
+
+        let scrolled = vm.borrow_mut().on_mouse_scroll(&mut cpu.borrow_mut(), x, y);
+        log_vector_output("Mouse", scrolled, &cpu.borrow());
+    });
+
+    // right clicks are for the ROM, not for the context menu
+    let on_context_menu = Closure::<dyn FnMut(_)>::new(move |event: web_sys::Event| {
+        event.prevent_default();
+    });
+
+    for name in ["pointermove", "pointerdown", "pointerup"] {
+        canvas
+            .add_event_listener_with_callback(name, on_pointer.as_ref().unchecked_ref())
+            .unwrap_or_else(|_| panic!("Cannot register `{}` handler", name));
+    }
+    canvas
+        .add_event_listener_with_callback("wheel", on_wheel.as_ref().unchecked_ref())
+        .expect("Cannot register `wheel` handler");
+    canvas
+        .add_event_listener_with_callback("contextmenu", on_context_menu.as_ref().unchecked_ref())
+        .expect("Cannot register `contextmenu` handler");
+
+    // the handlers live as long as the page
+    on_pointer.forget();
+    on_wheel.forget();
+    on_context_menu.forget();
+}
+
+/// Converts client coordinates into screen pixels. The canvas is scaled with
+/// CSS, so its size on the page doesn't match its size in pixels.
+fn screen_position(
+    canvas: &web_sys::HtmlCanvasElement,
+    client_x: i32,
+    client_y: i32,
+) -> (u16, u16) {
+    let rect = canvas.get_bounding_client_rect();
+    let scale_x = SCREEN_WIDTH as f64 / rect.width();
+    let scale_y = SCREEN_HEIGHT as f64 / rect.height();
+
+    let x = ((client_x as f64 - rect.left()) * scale_x).clamp(0.0, SCREEN_WIDTH as f64 - 1.0);
+    let y = ((client_y as f64 - rect.top()) * scale_y).clamp(0.0, SCREEN_HEIGHT as f64 - 1.0);
+    (x as u16, y as u16)
+}
+
+/// Converts `MouseEvent.buttons` into a mask of `MouseButton` values
+fn mouse_buttons(buttons: u16) -> u8 {
+    const MAPPING: [(u16, MouseButton); 3] = [
+        (0x01, MouseButton::Left),
+        (0x02, MouseButton::Right),
+        (0x04, MouseButton::Middle),
+    ];
+
+    MAPPING
+        .iter()
+        .filter(|(bit, _)| buttons & bit != 0)
+        .fold(0x00, |mask, (_, button)| mask | *button as u8)
+}
+
+/// Logs what the ROM wrote to the Console device
+fn log_console_output(output: &DeviceOutput) {
+    if !output.console_stdout.is_empty() {
+        let text = String::from_utf8_lossy(&output.console_stdout);
+        web_sys::console::log_1(&JsValue::from(text.as_ref()));
+    }
+    if !output.console_stderr.is_empty() {
+        let text = String::from_utf8_lossy(&output.console_stderr);
+        web_sys::console::error_1(&JsValue::from(text.as_ref()));
+    }
+}
+
+/// Logs the output of a device vector, or its fault
+fn log_vector_output(device: &str, result: core::result::Result<DeviceOutput, Trap>, cpu: &Cpu) {
+    let output = match result {
+        Ok(output) => output,
+        Err(trap) => {
+            web_sys::console::error_1(&JsValue::from(trap.to_string()));
+            return;
+        }
+    };
+    log_console_output(&output);
+    if !output.sys_stdout.is_empty() {
+        web_sys::console::log_1(&JsValue::from(output.sys_stdout.as_str()));
+    }
+    if output.is_paused {
+        web_sys::console::warn_1(&JsValue::from(format!(
+            "{} vector paused at {:04x} after {} cycles",
+            device,
+            cpu.pc(),
+            MAX_CYCLES_PER_VECTOR
+        )));
+    }
+}
+
+fn button_for_key(key: &str) -> Option<Button> {
+    match key {
+        "Control" => Some(Button::A),
+        "Alt" => Some(Button::B),
+        "Shift" => Some(Button::Select),
+        "Home" => Some(Button::Start),
+        "ArrowUp" => Some(Button::Up),
+        "ArrowDown" => Some(Button::Down),
+        "ArrowLeft" => Some(Button::Left),
+        "ArrowRight" => Some(Button::Right),
+        _ => None,
+    }
+}
+
+/// Returns the ASCII character for a `KeyboardEvent.key` value, if it has one
+fn ascii_for_key(key: &str) -> Option<u8> {
+    match key {
+        "Backspace" => Some(0x08),
+        "Tab" => Some(0x09),
+        "Enter" => Some(0x0d),
+        "Escape" => Some(0x1b),
+        "Delete" => Some(0x7f),
+        _ if key.len() == 1 && key.is_ascii() => Some(key.as_bytes()[0]),
+        _ => None,
+    }
+}
+
+/// Returns the buttons pressed in any gamepad, assuming the standard layout
+/// (https://w3c.github.io/gamepad/#remapping)
+fn gamepad_buttons() -> u8 {
+    const MAPPING: [(u32, Button); 8] = [
+        (0, Button::A),
+        (1, Button::B),
+        (8, Button::Select),
+        (9, Button::Start),
+        (12, Button::Up),
+        (13, Button::Down),
+        (14, Button::Left),
+        (15, Button::Right),
+    ];
+
+    let Ok(gamepads) = window().navigator().get_gamepads() else {
+        return 0x00;
+    };
+
+    let mut buttons = 0x00;
+    for gamepad in gamepads.iter() {
+        let Ok(gamepad) = gamepad.dyn_into::<web_sys::Gamepad>() else {
+            continue;
+        };
+        let gamepad_buttons = gamepad.buttons();
+        for (index, button) in MAPPING {
+            let is_pressed = gamepad_buttons
+                .get(index)
+                .dyn_into::<web_sys::GamepadButton>()
+                .is_ok_and(|b| b.pressed());
+            if is_pressed {
+                buttons |= button as u8;
+            }
+        }
+    }
+    buttons
+}
+
+/// Returns a listing of the ROM, one instruction per line
+#[wasm_bindgen]
+pub fn disassemble(rom: &[u8]) -> String {
+    disasm::disassemble(rom, 0x100)
+        .map(|instruction| format!("{}\n", instruction))
+        .collect()
+}
+
 fn render(vm: &mut Vm, ctx: &web_sys::CanvasRenderingContext2d, buffer: &mut DisplayBuffer) {
     let pixels = vm.pixels();
 
@@ -79,12 +482,11 @@ fn render(vm: &mut Vm, ctx: &web_sys::CanvasRenderingContext2d, buffer: &mut Dis
 }
 
 fn update_display_buffer(buffer: &mut DisplayBuffer, pixels: &DeviceBuffer) {
-    for i in 0..VIDEO_BUFFER_LEN {
-        let raw_color = pixels[i];
+    for (i, &raw_color) in pixels.iter().enumerate() {
         let color = THEME[raw_color as usize];
 
         let j = i * 4;
-        buffer[j + 0] = color.0; // r
+        buffer[j] = color.0; // r
         buffer[j + 1] = color.1; // g
         buffer[j + 2] = color.2; // b
         buffer[j + 3] = 0xFF; // alpha
@@ -0,0 +1,347 @@
+use super::Device;
+use crate::ports::{peek_short, poke_short};
+use crate::{DeviceDescription, PortDescription};
+use coco_core::{Cpu, Ports};
+
+/// Ports of one of the audio channels. Every channel has the same ports, in
+/// consecutive device pages starting at `0x30`.
+#[derive(Debug)]
+pub struct AudioPorts<const N: u8> {}
+
+impl<const N: u8> Ports for AudioPorts<N> {
+    const BASE: u8 = 0x30 + N * 0x10;
+}
+
+impl<const N: u8> AudioPorts<N> {
+    pub const DESCRIPTION: DeviceDescription = DeviceDescription {
+        name: ["Audio0", "Audio1", "Audio2", "Audio3"][N as usize],
+        base: Self::BASE,
+        ports: &[
+            PortDescription::short("vector", VECTOR),
+            PortDescription::short("position", POSITION),
+            PortDescription::byte("output", OUTPUT),
+            PortDescription::byte("wave", WAVE),
+            PortDescription::short("adsr", ADSR),
+            PortDescription::short("length", LENGTH),
+            PortDescription::short("address", ADDRESS),
+            PortDescription::byte("volume", VOLUME),
+            PortDescription::byte("pitch", PITCH),
+        ],
+    };
+}
+
+const VECTOR: u8 = 0x00;
+const POSITION: u8 = 0x02;
+const OUTPUT: u8 = 0x04;
+const WAVE: u8 = 0x05;
+const ADSR: u8 = 0x08;
+const LENGTH: u8 = 0x0a;
+const ADDRESS: u8 = 0x0c;
+const VOLUME: u8 = 0x0e;
+const PITCH: u8 = 0x0f;
+
+pub const AUDIO_CHANNELS: usize = 4;
+
+/// Frames per second of the PCM output
+pub const SAMPLE_RATE: u32 = 44_100;
+
+/// Number of frames in a step of an ADSR envelope (a 15th of a second)
+const ADSR_STEP: u32 = SAMPLE_RATE / 15;
+
+/// Samples up to this length are single-cycle waveforms
+const MAX_CYCLE_LENGTH: u16 = 0x100;
+
+/// Notes from this one up stop the channel
+const MAX_NOTE: u8 = 108;
+
+/// Frequency of MIDI note 0, in millihertz
+const NOTE_0_FREQUENCY: u64 = 8_176;
+
+/// Frequency ratio of each semitone within an octave, as 16.16 fixed point
+const SEMITONES: [u64; 12] = [
+    65536, 69433, 73562, 77936, 82570, 87480, 92682, 98193, 104032, 110218, 116772, 123715,
+];
+
+/// Full volume of an envelope
+const MAX_GAIN: i32 = 0x100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Envelope {
+    attack: u32,
+    decay: u32,
+    sustain: u32,
+    release: u32,
+}
+
+impl Envelope {
+    fn new(adsr: u16) -> Self {
+        let step = |shift: u16| ((adsr >> shift) & 0x0f) as u32 * ADSR_STEP;
+        Self {
+            attack: step(12),
+            decay: step(8),
+            sustain: step(4),
+            release: step(0),
+        }
+    }
+
+    /// Returns the gain `t` frames into a note, or `None` once the note has
+    /// been released. Without any ADSR the note plays at full volume.
+    fn gain(&self, t: u32) -> Option<i32> {
+        let half = MAX_GAIN / 2;
+        if *self == Self::new(0x0000) {
+            return Some(MAX_GAIN);
+        }
+
+        let mut t = t;
+        if t < self.attack {
+            return Some(MAX_GAIN * t as i32 / self.attack as i32);
+        }
+        t -= self.attack;
+        if t < self.decay {
+            return Some(MAX_GAIN - half * t as i32 / self.decay as i32);
+        }
+        t -= self.decay;
+        if t < self.sustain {
+            return Some(half);
+        }
+        t -= self.sustain;
+        if t < self.release {
+            return Some(half - half * t as i32 / self.release as i32);
+        }
+
+        None
+    }
+}
+
+/// Where the sound of a note comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Voice {
+    /// A sample in RAM
+    Sample,
+    /// A square wave that is high for `duty` 16ths of its period
+    Square {
+        duty: u8,
+    },
+    Triangle,
+    Saw,
+    /// Pseudo-random noise from a 15-bit LFSR, which shifts 16 times per
+    /// period
+    Noise {
+        lfsr: u16,
+    },
+}
+
+impl Voice {
+    /// Returns the voice selected by the `wave` port, if it's a valid one
+    fn new(wave: u8) -> Option<Self> {
+        match wave & 0x0f {
+            0x0 => Some(Self::Sample),
+            0x1 => Some(Self::Square {
+                duty: match wave >> 4 {
+                    0x0 => 0x8,
+                    duty => duty,
+                },
+            }),
+            0x2 => Some(Self::Triangle),
+            0x3 => Some(Self::Saw),
+            0x4 => Some(Self::Noise { lfsr: 0x7fff }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Note {
+    voice: Voice,
+    address: u16,
+    /// Length of the sample, or of a period of the oscillator for the other
+    /// voices
+    length: u16,
+    is_looping: bool,
+    envelope: Envelope,
+    /// Volume of the left and right sides, from `0x0` to `0xf`
+    volume: [i32; 2],
+    /// Position in the sample, as 16.16 fixed point
+    position: u64,
+    /// How much the position moves every frame, as 16.16 fixed point
+    advance: u64,
+    /// Number of frames played so far. It stops counting once it reaches
+    /// `u32::MAX` (about 27 hours), which is past the end of any envelope.
+    elapsed: u32,
+}
+
+impl Note {
+    /// Returns how far to move in a sample every frame for a MIDI note.
+    /// Single-cycle samples play once per period of the note; longer samples
+    /// are assumed to be recorded as middle C (note `60`) at `SAMPLE_RATE`.
+    fn advance(note: u8, length: u16) -> u64 {
+        let ratio = SEMITONES[note as usize % 12] << (note / 12);
+        if length <= MAX_CYCLE_LENGTH {
+            NOTE_0_FREQUENCY * ratio * length as u64 / (SAMPLE_RATE as u64 * 1000)
+        } else {
+            ratio >> 5
+        }
+    }
+
+    /// Returns the unsigned 8-bit value of the voice at the current position
+    fn peek(&self, cpu: &Cpu) -> u8 {
+        let offset = (self.position >> 16) as u16;
+        match self.voice {
+            Voice::Sample => cpu.ram_peek_byte(self.address.wrapping_add(offset)),
+            Voice::Square { duty } if offset < duty as u16 * 0x10 => 0xff,
+            Voice::Square { .. } => 0x00,
+            Voice::Triangle if offset < 0x80 => (offset * 2) as u8,
+            Voice::Triangle => ((0xff - offset) * 2) as u8,
+            Voice::Saw => offset as u8,
+            Voice::Noise { lfsr } if lfsr & 0x01 == 0x01 => 0xff,
+            Voice::Noise { .. } => 0x00,
+        }
+    }
+
+    /// Moves on to the next frame, and returns whether the note ended
+    fn step(&mut self) -> bool {
+        let previous = self.position;
+        self.position += self.advance;
+        self.elapsed = self.elapsed.saturating_add(1);
+
+        if let Voice::Noise { lfsr } = &mut self.voice {
+            if previous >> 20 != self.position >> 20 {
+                let bit = (*lfsr ^ (*lfsr >> 1)) & 0x01;
+                *lfsr = (*lfsr >> 1) | (bit << 14);
+            }
+        }
+
+        let end = (self.length as u64) << 16;
+        if self.position >= end {
+            // oscillators always loop
+            if !self.is_looping && self.voice == Voice::Sample {
+                return true;
+            }
+            self.position %= end;
+        }
+
+        false
+    }
+}
+
+#[derive(Debug)]
+pub struct AudioDevice {
+    channel: usize,
+    vector: u16,
+    note: Option<Note>,
+}
+
+impl AudioDevice {
+    pub fn new(channel: usize) -> Self {
+        Self {
+            channel,
+            vector: 0,
+            note: None,
+        }
+    }
+
+    /// Returns the address to call when a note ends, or zero if the ROM has
+    /// not set any
+    pub fn vector(&self) -> u16 {
+        self.vector
+    }
+
+    /// Adds the note being played to `buffer`, as interleaved stereo frames,
+    /// and returns whether it ended
+    pub fn render(&mut self, cpu: &mut Cpu, buffer: &mut [i16]) -> bool {
+        let Some(note) = &mut self.note else {
+            return false;
+        };
+
+        let mut has_ended = false;
+        let mut gain = 0;
+        for frame in buffer.chunks_exact_mut(2) {
+            let Some(note_gain) = note.envelope.gain(note.elapsed) else {
+                has_ended = true;
+                break;
+            };
+            gain = note_gain;
+
+            let sample = note.peek(cpu) as i32 - 0x80;
+            for (out, volume) in frame.iter_mut().zip(note.volume) {
+                *out = out.saturating_add(((sample * gain * volume) >> 6) as i16);
+            }
+
+            if note.step() {
+                has_ended = true;
+                break;
+            }
+        }
+
+        let position = (note.position >> 16) as u16;
+        let ports = page(cpu, self.channel);
+        if has_ended {
+            self.note = None;
+            poke_short(ports, POSITION, 0x0000);
+            ports[OUTPUT as usize] = 0x00;
+        } else {
+            poke_short(ports, POSITION, position);
+            ports[OUTPUT as usize] = gain.min(0xff) as u8;
+        }
+
+        has_ended
+    }
+
+    #[inline]
+    fn deo_vector(&mut self, cpu: &mut Cpu) {
+        let ports = page(cpu, self.channel);
+        self.vector = peek_short(ports, VECTOR);
+    }
+
+    /// Starts playing a note with the current ports, or stops the channel if
+    /// there's nothing to play
+    fn deo_pitch(&mut self, cpu: &mut Cpu) {
+        let ports = page(cpu, self.channel);
+        let pitch = ports[PITCH as usize];
+        let volume = ports[VOLUME as usize];
+        let voice = Voice::new(ports[WAVE as usize]);
+        let length = match voice {
+            Some(Voice::Sample) => peek_short(ports, LENGTH),
+            Some(_) => MAX_CYCLE_LENGTH,
+            None => 0,
+        };
+
+        let Some(voice) = voice.filter(|_| length > 0 && pitch & 0x7f < MAX_NOTE) else {
+            self.note = None;
+            return;
+        };
+
+        self.note = Some(Note {
+            voice,
+            address: peek_short(ports, ADDRESS),
+            length,
+            is_looping: pitch & 0x80 == 0,
+            envelope: Envelope::new(peek_short(ports, ADSR)),
+            volume: [(volume >> 4) as i32, (volume & 0x0f) as i32],
+            position: 0,
+            advance: Note::advance(pitch & 0x7f, length),
+            elapsed: 0,
+        });
+    }
+}
+
+impl Device for AudioDevice {
+    fn deo(&mut self, cpu: &mut Cpu, _: u8, target: u8) {
+        match target {
+            VECTOR => self.deo_vector(cpu),
+            PITCH => self.deo_pitch(cpu),
+            _ => {}
+        }
+    }
+}
+
+/// Returns the device page of an audio channel
+fn page(cpu: &mut Cpu, channel: usize) -> &mut [u8] {
+    match channel {
+        0 => cpu.device_page::<AudioPorts<0>>(),
+        1 => cpu.device_page::<AudioPorts<1>>(),
+        2 => cpu.device_page::<AudioPorts<2>>(),
+        3 => cpu.device_page::<AudioPorts<3>>(),
+        _ => unreachable!("there are only {} audio channels", AUDIO_CHANNELS),
+    }
+}
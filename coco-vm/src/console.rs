@@ -0,0 +1,90 @@
+use super::Device;
+use crate::ports::peek_short;
+use crate::{DeviceDescription, PortDescription};
+use coco_core::{Cpu, Ports};
+
+#[derive(Debug)]
+pub struct ConsolePorts {}
+
+impl Ports for ConsolePorts {
+    const BASE: u8 = 0x20;
+}
+
+impl ConsolePorts {
+    const VECTOR: u8 = 0x00;
+    const READ: u8 = 0x02;
+    const TYPE: u8 = 0x07;
+    const WRITE: u8 = 0x08;
+    const ERROR: u8 = 0x09;
+
+    pub const DESCRIPTION: DeviceDescription = DeviceDescription {
+        name: "Console",
+        base: Self::BASE,
+        ports: &[
+            PortDescription::short("vector", Self::VECTOR),
+            PortDescription::byte("read", Self::READ),
+            PortDescription::byte("type", Self::TYPE),
+            PortDescription::byte("write", Self::WRITE),
+            PortDescription::byte("error", Self::ERROR),
+        ],
+    };
+}
+
+/// Value of the `type` port for a byte read from stdin
+const TYPE_STDIN: u8 = 0x01;
+
+#[derive(Debug)]
+pub struct ConsoleDevice {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    vector: u16,
+}
+
+impl ConsoleDevice {
+    pub fn new() -> Self {
+        Self {
+            stdout: vec![],
+            stderr: vec![],
+            vector: 0,
+        }
+    }
+
+    pub fn vector(&self) -> u16 {
+        self.vector
+    }
+
+    /// Puts a byte read from stdin into the `read` port
+    pub fn set_input(&mut self, cpu: &mut Cpu, byte: u8) {
+        let ports = cpu.device_page::<ConsolePorts>();
+        ports[ConsolePorts::READ as usize] = byte;
+        ports[ConsolePorts::TYPE as usize] = TYPE_STDIN;
+    }
+
+    /// Returns the stdout buffer and flushes it
+    pub fn stdout(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.stdout)
+    }
+
+    /// Returns the stderr buffer and flushes it
+    pub fn stderr(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.stderr)
+    }
+
+    #[inline]
+    fn deo_vector(&mut self, cpu: &mut Cpu) {
+        let ports = cpu.device_page::<ConsolePorts>();
+        self.vector = peek_short(ports, ConsolePorts::VECTOR);
+    }
+}
+
+impl Device for ConsoleDevice {
+    fn deo(&mut self, cpu: &mut Cpu, _: u8, target: u8) {
+        let ports = cpu.device_page::<ConsolePorts>();
+        match target {
+            ConsolePorts::VECTOR => self.deo_vector(cpu),
+            ConsolePorts::WRITE => self.stdout.push(ports[ConsolePorts::WRITE as usize]),
+            ConsolePorts::ERROR => self.stderr.push(ports[ConsolePorts::ERROR as usize]),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use super::Device;
+use crate::{DeviceDescription, PortDescription};
+use coco_core::{Cpu, Ports};
+
+#[derive(Debug)]
+pub struct ControllerPorts {}
+
+impl Ports for ControllerPorts {
+    const BASE: u8 = 0x80;
+}
+
+impl ControllerPorts {
+    const VECTOR: u8 = 0x00;
+    const BUTTON: u8 = 0x02;
+    const KEY: u8 = 0x03;
+
+    pub const DESCRIPTION: DeviceDescription = DeviceDescription {
+        name: "Controller",
+        base: Self::BASE,
+        ports: &[
+            PortDescription::short("vector", Self::VECTOR),
+            PortDescription::byte("button", Self::BUTTON),
+            PortDescription::byte("key", Self::KEY),
+        ],
+    };
+}
+
+/// The buttons of the controller, as bits of the `button` port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Button {
+    A = 0x01,
+    B = 0x02,
+    Select = 0x04,
+    Start = 0x08,
+    Up = 0x10,
+    Down = 0x20,
+    Left = 0x40,
+    Right = 0x80,
+}
+
+#[derive(Debug)]
+pub struct ControllerDevice {
+    vector: u16,
+}
+
+impl ControllerDevice {
+    pub fn new() -> Self {
+        Self { vector: 0 }
+    }
+
+    pub fn vector(&self) -> u16 {
+        self.vector
+    }
+
+    /// Sets the state of every button, and returns whether it changed
+    pub fn set_buttons(&mut self, cpu: &mut Cpu, buttons: u8) -> bool {
+        let ports = cpu.device_page::<ControllerPorts>();
+        let has_changed = ports[ControllerPorts::BUTTON as usize] != buttons;
+        ports[ControllerPorts::BUTTON as usize] = buttons;
+
+        has_changed
+    }
+
+    pub fn set_key(&mut self, cpu: &mut Cpu, key: u8) {
+        let ports = cpu.device_page::<ControllerPorts>();
+        ports[ControllerPorts::KEY as usize] = key;
+    }
+
+    #[inline]
+    fn deo_vector(&mut self, cpu: &mut Cpu) {
+        let ports = cpu.device_page::<ControllerPorts>();
+        let hi = ports[ControllerPorts::VECTOR as usize];
+        let lo = ports[ControllerPorts::VECTOR as usize + 1];
+
+        self.vector = u16::from_be_bytes([hi, lo]);
+    }
+}
+
+impl Device for ControllerDevice {
+    fn deo(&mut self, cpu: &mut Cpu, _: u8, target: u8) {
+        if target == ControllerPorts::VECTOR {
+            self.deo_vector(cpu);
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::Device;
+use crate::ports::poke_short;
+use crate::{DeviceDescription, PortDescription};
+use coco_core::{Cpu, Ports};
+
+#[derive(Debug)]
+pub struct DatetimePorts {}
+
+impl Ports for DatetimePorts {
+    const BASE: u8 = 0xc0;
+}
+
+impl DatetimePorts {
+    const YEAR: u8 = 0x00;
+    const MONTH: u8 = 0x02;
+    const DAY: u8 = 0x03;
+    const HOUR: u8 = 0x04;
+    const MINUTE: u8 = 0x05;
+    const SECOND: u8 = 0x06;
+    const DOTW: u8 = 0x07;
+    const DOTY: u8 = 0x08;
+    const ISDST: u8 = 0x0a;
+
+    pub const DESCRIPTION: DeviceDescription = DeviceDescription {
+        name: "DateTime",
+        base: Self::BASE,
+        ports: &[
+            PortDescription::short("year", Self::YEAR),
+            PortDescription::byte("month", Self::MONTH),
+            PortDescription::byte("day", Self::DAY),
+            PortDescription::byte("hour", Self::HOUR),
+            PortDescription::byte("minute", Self::MINUTE),
+            PortDescription::byte("second", Self::SECOND),
+            PortDescription::byte("dotw", Self::DOTW),
+            PortDescription::short("doty", Self::DOTY),
+            PortDescription::byte("isdst", Self::ISDST),
+        ],
+    };
+}
+
+/// A date and time, laid out like the ports of the DateTime device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DateTime {
+    pub year: u16,
+    /// Month of the year, from `0` (January) to `11` (December)
+    pub month: u8,
+    /// Day of the month, from `1` to `31`
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    /// Day of the week, from `0` (Sunday) to `6` (Saturday)
+    pub weekday: u8,
+    /// Day of the year, from `0` to `365`
+    pub yearday: u16,
+    /// Whether daylight saving time is in effect
+    pub is_dst: bool,
+}
+
+impl DateTime {
+    /// Returns the UTC date and time of a Unix timestamp
+    pub fn from_unix_seconds(seconds: i64) -> Self {
+        let days = seconds.div_euclid(86_400);
+        let time = seconds.rem_euclid(86_400);
+
+        // civil from days, see http://howardhinnant.github.io/date_algorithms.html
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 2 } else { mp - 10 };
+        let year = yoe + era * 400 + if month < 2 { 1 } else { 0 };
+
+        Self {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (time / 3_600) as u8,
+            minute: (time % 3_600 / 60) as u8,
+            second: (time % 60) as u8,
+            weekday: (days + 4).rem_euclid(7) as u8,
+            yearday: yearday(year as u16, month as u8, day as u8),
+            is_dst: false,
+        }
+    }
+}
+
+/// Returns the day of the year, from `0`, of a date with a `0`-based month
+pub fn yearday(year: u16, month: u8, day: u8) -> u16 {
+    const DAYS_BEFORE_MONTH: [u16; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+    let is_leap = (year.is_multiple_of(4) && !year.is_multiple_of(100)) || year.is_multiple_of(400);
+    let leap_day = if is_leap && month > 1 { 1 } else { 0 };
+
+    // days are counted from 1, but a bad clock could still give a 0
+    DAYS_BEFORE_MONTH[month as usize % 12] + leap_day + (day as u16).saturating_sub(1)
+}
+
+/// Tells the DateTime device what time it is.
+pub trait Clock: fmt::Debug {
+    fn now(&mut self) -> DateTime;
+}
+
+/// The clock of the host, in UTC.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&mut self) -> DateTime {
+        let seconds = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(elapsed) => elapsed.as_secs() as i64,
+            Err(err) => -(err.duration().as_secs() as i64),
+        };
+        DateTime::from_unix_seconds(seconds)
+    }
+}
+
+/// A clock that is stopped at a given time, for deterministic runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedClock(pub DateTime);
+
+impl Clock for FixedClock {
+    fn now(&mut self) -> DateTime {
+        self.0
+    }
+}
+
+#[derive(Debug)]
+pub struct DatetimeDevice {
+    clock: Box<dyn Clock>,
+}
+
+impl DatetimeDevice {
+    pub fn new() -> Self {
+        Self {
+            clock: Box::new(SystemClock),
+        }
+    }
+
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Writes the current time into the ports
+    fn refresh(&mut self, cpu: &mut Cpu) {
+        let now = self.clock.now();
+        let ports = cpu.device_page::<DatetimePorts>();
+
+        poke_short(ports, DatetimePorts::YEAR, now.year);
+        ports[DatetimePorts::MONTH as usize] = now.month;
+        ports[DatetimePorts::DAY as usize] = now.day;
+        ports[DatetimePorts::HOUR as usize] = now.hour;
+        ports[DatetimePorts::MINUTE as usize] = now.minute;
+        ports[DatetimePorts::SECOND as usize] = now.second;
+        ports[DatetimePorts::DOTW as usize] = now.weekday;
+        poke_short(ports, DatetimePorts::DOTY, now.yearday);
+        ports[DatetimePorts::ISDST as usize] = now.is_dst as u8;
+    }
+}
+
+impl Device for DatetimeDevice {
+    fn deo(&mut self, _: &mut Cpu, _: u8, _: u8) {}
+
+    fn dei(&mut self, cpu: &mut Cpu, _: u8, _: u8) {
+        // every port is refreshed, so shorts and the rest of the date match
+        self.refresh(cpu);
+    }
+}
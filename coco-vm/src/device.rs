@@ -0,0 +1,96 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::DEVICES;
+use coco_core::{Cpu, Fault};
+
+/// A peripheral that lives in one of the 16 device pages. `base` is the
+/// address of the page the device is plugged into, and its ports are the
+/// bytes of that page, which it can reach with `Cpu::device_page_at(base)`.
+pub trait Device: Any + fmt::Debug {
+    /// Called when the ROM reads the port at `target`, an offset within the
+    /// device page, right before the value of the port is pushed. Devices can
+    /// update the port here to return a live value. Shorts are read with a
+    /// single call, for the port of their high byte.
+    fn dei(&mut self, _cpu: &mut Cpu, _base: u8, _target: u8) {}
+    /// Called when the ROM writes to the port at `target`, an offset within
+    /// the device page. Shorts are written with a single call, for the port
+    /// of their high byte.
+    fn deo(&mut self, cpu: &mut Cpu, base: u8, target: u8);
+}
+
+/// Shares a device with the host, so it can look at the device's state after
+/// plugging it into the VM.
+impl<D: Device> Device for Rc<RefCell<D>> {
+    fn dei(&mut self, cpu: &mut Cpu, base: u8, target: u8) {
+        self.borrow_mut().dei(cpu, base, target)
+    }
+
+    fn deo(&mut self, cpu: &mut Cpu, base: u8, target: u8) {
+        self.borrow_mut().deo(cpu, base, target)
+    }
+}
+
+/// Maps the 16 device pages to the devices plugged into them, and routes the
+/// ports the ROM reads and writes to those devices.
+#[derive(Debug, Default)]
+pub struct DeviceRegistry {
+    slots: [Option<Box<dyn Device>>; 0x10],
+}
+
+impl DeviceRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns whether a built-in device of the VM (one of `DEVICES`) uses
+    /// the page at `base`
+    pub fn is_reserved(base: u8) -> bool {
+        DEVICES.iter().any(|device| device.base == base & 0xf0)
+    }
+
+    /// Plugs a device into the page at `base`, and returns the device that
+    /// was plugged there before, if any
+    pub fn insert(&mut self, base: u8, device: Box<dyn Device>) -> Option<Box<dyn Device>> {
+        self.slots[(base >> 4) as usize].replace(device)
+    }
+
+    /// Unplugs the device at `base`, if any
+    pub fn remove(&mut self, base: u8) -> Option<Box<dyn Device>> {
+        self.slots[(base >> 4) as usize].take()
+    }
+
+    /// Returns the device plugged into the page at `base`, if it's a `D`
+    pub fn get<D: Device>(&self, base: u8) -> Option<&D> {
+        let device: &dyn Any = self.slots[(base >> 4) as usize].as_deref()?;
+        device.downcast_ref()
+    }
+
+    /// Returns the device plugged into the page at `base`, if it's a `D`
+    pub fn get_mut<D: Device>(&mut self, base: u8) -> Option<&mut D> {
+        let device: &mut dyn Any = self.slots[(base >> 4) as usize].as_deref_mut()?;
+        device.downcast_mut()
+    }
+
+    /// Lets the device of the port `target` update it before it's read
+    pub fn dei(&mut self, cpu: &mut Cpu, target: u8) -> Result<(), Fault> {
+        let device = self.device_of(target)?;
+        device.dei(cpu, target & 0xf0, target & 0x0f);
+        Ok(())
+    }
+
+    /// Tells the device of the port `target` that it has been written to
+    pub fn deo(&mut self, cpu: &mut Cpu, target: u8) -> Result<(), Fault> {
+        let device = self.device_of(target)?;
+        device.deo(cpu, target & 0xf0, target & 0x0f);
+        Ok(())
+    }
+
+    fn device_of(&mut self, target: u8) -> Result<&mut Box<dyn Device>, Fault> {
+        self.slots[(target >> 4) as usize]
+            .as_mut()
+            .ok_or(Fault::UnknownDevice(target))
+    }
+}
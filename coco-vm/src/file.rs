@@ -0,0 +1,190 @@
+use super::Device;
+use crate::ports::{peek_short, poke_short};
+use crate::{DeviceDescription, PortDescription, Storage};
+use coco_core::{Cpu, Ports};
+
+#[derive(Debug)]
+pub struct FilePorts {}
+
+impl Ports for FilePorts {
+    const BASE: u8 = 0xa0;
+}
+
+impl FilePorts {
+    const VECTOR: u8 = 0x00;
+    const SUCCESS: u8 = 0x02;
+    const STAT: u8 = 0x04;
+    const DELETE: u8 = 0x06;
+    const APPEND: u8 = 0x07;
+    const NAME: u8 = 0x08;
+    const LENGTH: u8 = 0x0a;
+    const READ: u8 = 0x0c;
+    const WRITE: u8 = 0x0e;
+
+    pub const DESCRIPTION: DeviceDescription = DeviceDescription {
+        name: "File",
+        base: Self::BASE,
+        ports: &[
+            PortDescription::short("vector", Self::VECTOR),
+            PortDescription::short("success", Self::SUCCESS),
+            PortDescription::short("stat", Self::STAT),
+            PortDescription::byte("delete", Self::DELETE),
+            PortDescription::byte("append", Self::APPEND),
+            PortDescription::short("name", Self::NAME),
+            PortDescription::short("length", Self::LENGTH),
+            PortDescription::short("read", Self::READ),
+            PortDescription::short("write", Self::WRITE),
+        ],
+    };
+}
+
+/// Longest file name a ROM can use, in bytes
+const MAX_NAME_LEN: u16 = 0x100;
+
+#[derive(Debug)]
+pub struct FileDevice {
+    storage: Option<Box<dyn Storage>>,
+    name: Option<String>,
+    /// Contents of the file being read, and how much of it has been read
+    reading: Option<(Vec<u8>, usize)>,
+    /// Whether the file has been written to since it was named, so further
+    /// writes append to it
+    is_writing: bool,
+}
+
+impl FileDevice {
+    pub fn new() -> Self {
+        Self {
+            storage: None,
+            name: None,
+            reading: None,
+            is_writing: false,
+        }
+    }
+
+    pub fn set_storage(&mut self, storage: Box<dyn Storage>) {
+        self.storage = Some(storage);
+    }
+
+    /// Forgets about the file being read or written
+    fn reset(&mut self) {
+        self.reading = None;
+        self.is_writing = false;
+    }
+
+    fn deo_name(&mut self, cpu: &mut Cpu) {
+        let addr = peek_short(cpu.device_page::<FilePorts>(), FilePorts::NAME);
+        let name: Vec<u8> = (0..MAX_NAME_LEN)
+            .map(|i| cpu.ram_peek_byte(addr.wrapping_add(i)))
+            .take_while(|&byte| byte != 0x00)
+            .collect();
+
+        self.name = String::from_utf8(name).ok();
+        self.reset();
+    }
+
+    /// Reads the next chunk of the file into memory, and returns its length
+    fn read(&mut self, cpu: &mut Cpu, addr: u16, length: u16) -> Option<u16> {
+        let name = self.name.as_ref()?;
+        let storage = self.storage.as_mut()?;
+        if self.reading.is_none() {
+            self.reading = Some((storage.read(name).ok()?, 0));
+            self.is_writing = false;
+        }
+
+        let (contents, offset) = self.reading.as_mut()?;
+        let end = (*offset + length as usize).min(contents.len());
+        let chunk = &contents[*offset..end];
+        for (i, &byte) in chunk.iter().enumerate() {
+            cpu.ram_poke_byte(addr.wrapping_add(i as u16), byte);
+        }
+        *offset = end;
+
+        Some(chunk.len() as u16)
+    }
+
+    /// Writes memory into the file, and returns how many bytes were written
+    fn write(&mut self, cpu: &mut Cpu, addr: u16, length: u16) -> Option<u16> {
+        let name = self.name.as_ref()?;
+        let storage = self.storage.as_mut()?;
+        let data: Vec<u8> = (0..length)
+            .map(|i| cpu.ram_peek_byte(addr.wrapping_add(i)))
+            .collect();
+
+        let append =
+            self.is_writing || cpu.device_page::<FilePorts>()[FilePorts::APPEND as usize] == 0x01;
+        // the file changes, so reads have to start over from its new contents
+        self.reading = None;
+        storage.write(name, &data, append).ok()?;
+        self.is_writing = true;
+
+        Some(length)
+    }
+
+    /// Writes the size of the file into memory as hex digits. Sizes that
+    /// don't fit are written as `?`, and missing files as `!`.
+    fn stat(&mut self, cpu: &mut Cpu, addr: u16, length: u16) -> Option<u16> {
+        let name = self.name.as_ref()?;
+        let storage = self.storage.as_mut()?;
+        let length = length as usize;
+
+        let stat = match storage.size(name) {
+            Ok(size) => {
+                let digits = format!("{:0length$x}", size, length = length);
+                match digits.len() > length {
+                    true => "?".repeat(length),
+                    false => digits,
+                }
+            }
+            Err(_) => "!".repeat(length),
+        };
+        for (i, byte) in stat.bytes().enumerate() {
+            cpu.ram_poke_byte(addr.wrapping_add(i as u16), byte);
+        }
+
+        Some(length as u16)
+    }
+
+    fn delete(&mut self) -> Option<u16> {
+        let name = self.name.as_ref()?;
+        let storage = self.storage.as_mut()?;
+        storage.delete(name).ok()?;
+
+        Some(1)
+    }
+
+    /// Runs an operation that takes the `length` and an address from a port,
+    /// and puts its result in the `success` port
+    fn deo_operation(
+        &mut self,
+        cpu: &mut Cpu,
+        port: u8,
+        operation: fn(&mut Self, &mut Cpu, u16, u16) -> Option<u16>,
+    ) {
+        let ports = cpu.device_page::<FilePorts>();
+        let addr = peek_short(ports, port);
+        let length = peek_short(ports, FilePorts::LENGTH);
+
+        let success = operation(self, cpu, addr, length).unwrap_or(0);
+        poke_short(cpu.device_page::<FilePorts>(), FilePorts::SUCCESS, success);
+    }
+
+    fn deo_delete(&mut self, cpu: &mut Cpu) {
+        let success = self.delete().unwrap_or(0);
+        self.reset();
+        poke_short(cpu.device_page::<FilePorts>(), FilePorts::SUCCESS, success);
+    }
+}
+
+impl Device for FileDevice {
+    fn deo(&mut self, cpu: &mut Cpu, _: u8, target: u8) {
+        match target {
+            FilePorts::NAME => self.deo_name(cpu),
+            FilePorts::READ => self.deo_operation(cpu, FilePorts::READ, Self::read),
+            FilePorts::WRITE => self.deo_operation(cpu, FilePorts::WRITE, Self::write),
+            FilePorts::STAT => self.deo_operation(cpu, FilePorts::STAT, Self::stat),
+            FilePorts::DELETE => self.deo_delete(cpu),
+            _ => {}
+        }
+    }
+}
@@ -1,77 +1,362 @@
+mod audio;
+mod console;
+mod controller;
+mod datetime;
+mod device;
+mod file;
+mod mouse;
+mod ports;
+mod storage;
 mod system;
 mod video;
 
-use coco_core::{Cpu, Machine, Ports};
+use audio::{AudioDevice, AudioPorts};
+use coco_core::{Cpu, Fault, Machine, Ports, RunStatus, Trap};
+use console::{ConsoleDevice, ConsolePorts};
+use controller::{ControllerDevice, ControllerPorts};
+use datetime::{DatetimeDevice, DatetimePorts};
+use file::{FileDevice, FilePorts};
+use mouse::{MouseDevice, MousePorts};
 use system::{SystemDevice, SystemPorts};
 use video::{VideoDevice, VideoPorts};
 
-pub use video::{VideoBuffer, SCREEN_HEIGHT, SCREEN_WIDTH, VIDEO_BUFFER_LEN};
+pub use audio::{AUDIO_CHANNELS, SAMPLE_RATE};
+pub use controller::Button;
+pub use datetime::{yearday, Clock, DateTime, FixedClock, SystemClock};
+pub use device::{Device, DeviceRegistry};
+pub use mouse::MouseButton;
+pub use ports::{tal_header, DeviceDescription, PortDescription, DEVICES};
+pub use storage::{DirectoryStorage, MemoryStorage, Storage};
+pub use video::{Rgb, VideoBuffer, SCREEN_HEIGHT, SCREEN_WIDTH, THEME, VIDEO_BUFFER_LEN};
 
-trait Device {
-    #[allow(dead_code)]
-    fn dei(&mut self, cpu: &mut Cpu, target: u8);
-    fn deo(&mut self, cpu: &mut Cpu, target: u8);
-}
+/// Max. number of instructions a vector can execute before being paused
+pub const MAX_CYCLES_PER_VECTOR: usize = 1_000_000;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct DeviceOutput {
+    /// Whether the ROM has asked to stop, by writing to the System `state`
+    /// port
     pub shall_halt: bool,
+    /// Exit code the ROM has halted with, the value written to the System
+    /// `state` port without its top bit (so `0x80` halts with code zero)
+    pub exit_code: u8,
+    /// Whether the last vector exhausted its cycle budget and was paused
+    pub is_paused: bool,
     pub sys_stdout: String,
-}
-
-impl core::default::Default for DeviceOutput {
-    fn default() -> Self {
-        DeviceOutput {
-            shall_halt: false,
-            sys_stdout: String::from(""),
-        }
-    }
+    /// Bytes written to the Console `write` port
+    pub console_stdout: Vec<u8>,
+    /// Bytes written to the Console `error` port
+    pub console_stderr: Vec<u8>,
 }
 
 #[derive(Debug)]
 pub struct Vm {
-    video: VideoDevice,
-    system: SystemDevice,
+    /// Every device, built-in or plugged in by the host
+    devices: DeviceRegistry,
+    is_paused: bool,
 }
 
 impl Machine for Vm {
-    fn dei(&mut self, _: &mut Cpu, _: u8) {}
-    fn deo(&mut self, cpu: &mut Cpu, target: u8) {
-        let offset = target & 0x0f;
-        match target & 0xf0 {
-            SystemPorts::BASE => self.system.deo(cpu, offset),
-            VideoPorts::BASE => self.video.deo(cpu, offset),
-            _ => unimplemented!(),
-        }
+    fn dei(&mut self, cpu: &mut Cpu, target: u8) -> Result<(), Fault> {
+        self.devices.dei(cpu, target)
+    }
+
+    fn deo(&mut self, cpu: &mut Cpu, target: u8) -> Result<(), Fault> {
+        self.devices.deo(cpu, target)
+    }
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl Vm {
     pub fn new() -> Self {
+        let mut devices = DeviceRegistry::new();
+        devices.insert(SystemPorts::BASE, Box::new(SystemDevice::new()));
+        devices.insert(VideoPorts::BASE, Box::new(VideoDevice::new()));
+        devices.insert(ConsolePorts::BASE, Box::new(ConsoleDevice::new()));
+        for channel in 0..AUDIO_CHANNELS {
+            devices.insert(audio_base(channel), Box::new(AudioDevice::new(channel)));
+        }
+        devices.insert(ControllerPorts::BASE, Box::new(ControllerDevice::new()));
+        devices.insert(MousePorts::BASE, Box::new(MouseDevice::new()));
+        devices.insert(FilePorts::BASE, Box::new(FileDevice::new()));
+        devices.insert(DatetimePorts::BASE, Box::new(DatetimeDevice::new()));
+
         Self {
-            video: VideoDevice::new(),
-            system: SystemDevice::new(),
+            devices,
+            is_paused: false,
+        }
+    }
+
+    /// Gives the File device access to a storage. Without one, every file
+    /// operation fails.
+    pub fn with_storage(mut self, storage: impl Storage + 'static) -> Self {
+        self.file().set_storage(Box::new(storage));
+        self
+    }
+
+    /// Sets the clock of the DateTime device, which is the host's clock in
+    /// UTC by default
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.datetime().set_clock(Box::new(clock));
+        self
+    }
+
+    /// Seeds the generator behind the System `random` port, for hosts with a
+    /// better source of entropy than the default one, or for deterministic
+    /// runs
+    pub fn with_seed(mut self, seed: u16) -> Self {
+        self.system().set_seed(seed);
+        self
+    }
+
+    /// Plugs a custom device into the page at `base` (e.g. `0xe0`), so the
+    /// ROM can talk to it through the ports of that page
+    ///
+    /// # Panics
+    ///
+    /// Panics if the page is used by a built-in device.
+    pub fn with_device(mut self, base: u8, device: impl Device) -> Self {
+        assert_unreserved(base);
+        self.devices.insert(base, Box::new(device));
+        self
+    }
+
+    /// Unplugs the custom device at `base`, if any
+    ///
+    /// # Panics
+    ///
+    /// Panics if the page is used by a built-in device.
+    pub fn remove_device(&mut self, base: u8) -> Option<Box<dyn Device>> {
+        assert_unreserved(base);
+        self.devices.remove(base)
+    }
+
+    /// Returns the device plugged into the page at `base`, if it's a `D`
+    pub fn device<D: Device>(&self, base: u8) -> Option<&D> {
+        self.devices.get(base)
+    }
+
+    /// Returns the device plugged into the page at `base`, if it's a `D`
+    pub fn device_mut<D: Device>(&mut self, base: u8) -> Option<&mut D> {
+        self.devices.get_mut(base)
+    }
+
+    pub fn on_reset(&mut self, cpu: &mut Cpu) -> Result<DeviceOutput, Trap> {
+        self.run(cpu, 0x100)?;
+        Ok(self.output())
+    }
+
+    pub fn on_video(&mut self, cpu: &mut Cpu) -> Result<DeviceOutput, Trap> {
+        let vector = self.video().vector();
+        self.run_vector(cpu, vector)?;
+        Ok(self.output())
+    }
+
+    /// Sends a byte read from stdin to the console vector
+    pub fn on_console(&mut self, cpu: &mut Cpu, byte: u8) -> Result<DeviceOutput, Trap> {
+        self.console().set_input(cpu, byte);
+        let vector = self.console().vector();
+        self.run_vector(cpu, vector)?;
+        Ok(self.output())
+    }
+
+    /// Sets which buttons are pressed (a mask of `Button` values), and calls
+    /// the controller vector if that changed
+    pub fn on_buttons(&mut self, cpu: &mut Cpu, buttons: u8) -> Result<DeviceOutput, Trap> {
+        let controller = self.controller();
+        let vector = match controller.set_buttons(cpu, buttons) {
+            true => controller.vector(),
+            false => 0x0000,
+        };
+        self.run_vector(cpu, vector)?;
+        Ok(self.output())
+    }
+
+    /// Sends a key press (an ASCII character) to the controller vector. The
+    /// key port is cleared afterwards, since keys don't stay pressed.
+    pub fn on_key(&mut self, cpu: &mut Cpu, key: u8) -> Result<DeviceOutput, Trap> {
+        self.controller().set_key(cpu, key);
+        let vector = self.controller().vector();
+        let result = self.run_vector(cpu, vector);
+        self.controller().set_key(cpu, 0x00);
+
+        result?;
+        Ok(self.output())
+    }
+
+    /// Moves the pointer to the given screen coordinates, and calls the mouse
+    /// vector if it moved
+    pub fn on_mouse_move(&mut self, cpu: &mut Cpu, x: u16, y: u16) -> Result<DeviceOutput, Trap> {
+        let mouse = self.mouse();
+        let vector = match mouse.set_position(cpu, x, y) {
+            true => mouse.vector(),
+            false => 0x0000,
+        };
+        self.run_vector(cpu, vector)?;
+        Ok(self.output())
+    }
+
+    /// Sets which mouse buttons are pressed (a mask of `MouseButton` values),
+    /// and calls the mouse vector if that changed
+    pub fn on_mouse_buttons(&mut self, cpu: &mut Cpu, buttons: u8) -> Result<DeviceOutput, Trap> {
+        let mouse = self.mouse();
+        let vector = match mouse.set_buttons(cpu, buttons) {
+            true => mouse.vector(),
+            false => 0x0000,
+        };
+        self.run_vector(cpu, vector)?;
+        Ok(self.output())
+    }
+
+    /// Sends a scroll to the mouse vector. The scroll ports are cleared
+    /// afterwards, since a scroll is not a lasting state.
+    pub fn on_mouse_scroll(&mut self, cpu: &mut Cpu, x: i16, y: i16) -> Result<DeviceOutput, Trap> {
+        self.mouse().set_scroll(cpu, x, y);
+        let vector = self.mouse().vector();
+        let result = self.run_vector(cpu, vector);
+        self.mouse().set_scroll(cpu, 0, 0);
+
+        result?;
+        Ok(self.output())
+    }
+
+    /// Calls a vector on behalf of a custom device, e.g. when a sensor has a
+    /// new reading. Does nothing if `vector` is zero.
+    pub fn on_vector(&mut self, cpu: &mut Cpu, vector: u16) -> Result<DeviceOutput, Trap> {
+        self.run_vector(cpu, vector)?;
+        Ok(self.output())
+    }
+
+    /// Mixes the audio channels into `buffer`, as interleaved stereo frames at
+    /// `SAMPLE_RATE`, and calls the vector of every channel whose note ended
+    pub fn on_audio(&mut self, cpu: &mut Cpu, buffer: &mut [i16]) -> Result<DeviceOutput, Trap> {
+        buffer.fill(0);
+        let mut vectors = vec![];
+        for channel in 0..AUDIO_CHANNELS {
+            let channel = self.audio(channel);
+            if channel.render(cpu, buffer) {
+                vectors.push(channel.vector());
+            }
         }
+
+        self.is_paused = false;
+        for vector in vectors {
+            self.run_vector(cpu, vector)?;
+            if self.is_paused {
+                break;
+            }
+        }
+        Ok(self.output())
     }
 
-    pub fn on_reset(&mut self, cpu: &mut Cpu) -> DeviceOutput {
-        cpu.run(0x100, self);
-        self.output()
+    /// Runs a device vector, unless the ROM hasn't set it
+    fn run_vector(&mut self, cpu: &mut Cpu, vector: u16) -> Result<(), Trap> {
+        if vector == 0x0000 {
+            self.is_paused = false;
+            return Ok(());
+        }
+        self.run(cpu, vector)
     }
 
-    pub fn on_video(&mut self, cpu: &mut Cpu) -> DeviceOutput {
-        cpu.run(self.video.vector(), self);
-        self.output()
+    /// Runs the code at the given address. If it faults and the ROM has set
+    /// a System vector, the fault is handed over to it instead.
+    fn run(&mut self, cpu: &mut Cpu, addr: u16) -> Result<(), Trap> {
+        let status = match cpu.run_for(addr, self, MAX_CYCLES_PER_VECTOR) {
+            RunStatus::Fault(trap) if self.system().vector() != 0 => {
+                cpu.load_fault_frame(&trap);
+                let vector = self.system().vector();
+                cpu.run_for(vector, self, MAX_CYCLES_PER_VECTOR)
+            }
+            status => status,
+        };
+
+        self.is_paused = status == RunStatus::BudgetExhausted;
+        match status {
+            RunStatus::Fault(trap) => Err(trap),
+            _ => Ok(()),
+        }
     }
 
     pub fn pixels(&mut self) -> &VideoBuffer {
-        &self.video.pixels()
+        self.video().pixels()
+    }
+
+    /// Returns the background and foreground layers, in that order
+    pub fn layers(&self) -> &[VideoBuffer; 2] {
+        &self.builtin::<VideoDevice>(VideoPorts::BASE).layers
     }
 
     pub fn output(&mut self) -> DeviceOutput {
         DeviceOutput {
-            shall_halt: false,
-            sys_stdout: self.system.stdout(),
+            shall_halt: self.system().is_halted(),
+            exit_code: self.system().exit_code(),
+            is_paused: self.is_paused,
+            sys_stdout: self.system().stdout(),
+            console_stdout: self.console().stdout(),
+            console_stderr: self.console().stderr(),
         }
     }
+
+    /// Returns a built-in device, which is always plugged into its page
+    fn builtin<D: Device>(&self, base: u8) -> &D {
+        self.devices
+            .get(base)
+            .expect("built-in devices can't be unplugged")
+    }
+
+    fn builtin_mut<D: Device>(&mut self, base: u8) -> &mut D {
+        self.devices
+            .get_mut(base)
+            .expect("built-in devices can't be unplugged")
+    }
+
+    fn system(&mut self) -> &mut SystemDevice {
+        self.builtin_mut(SystemPorts::BASE)
+    }
+
+    fn video(&mut self) -> &mut VideoDevice {
+        self.builtin_mut(VideoPorts::BASE)
+    }
+
+    fn console(&mut self) -> &mut ConsoleDevice {
+        self.builtin_mut(ConsolePorts::BASE)
+    }
+
+    fn audio(&mut self, channel: usize) -> &mut AudioDevice {
+        self.builtin_mut(audio_base(channel))
+    }
+
+    fn controller(&mut self) -> &mut ControllerDevice {
+        self.builtin_mut(ControllerPorts::BASE)
+    }
+
+    fn mouse(&mut self) -> &mut MouseDevice {
+        self.builtin_mut(MousePorts::BASE)
+    }
+
+    fn file(&mut self) -> &mut FileDevice {
+        self.builtin_mut(FilePorts::BASE)
+    }
+
+    fn datetime(&mut self) -> &mut DatetimeDevice {
+        self.builtin_mut(DatetimePorts::BASE)
+    }
+}
+
+/// Returns the page of an audio channel
+fn audio_base(channel: usize) -> u8 {
+    AudioPorts::<0>::BASE + channel as u8 * 0x10
+}
+
+fn assert_unreserved(base: u8) {
+    assert!(
+        !DeviceRegistry::is_reserved(base),
+        "device page {:02x} is used by a built-in device",
+        base & 0xf0
+    );
 }
@@ -0,0 +1,97 @@
+use super::Device;
+use crate::ports::{peek_short, poke_short};
+use crate::{DeviceDescription, PortDescription};
+use coco_core::{Cpu, Ports};
+
+#[derive(Debug)]
+pub struct MousePorts {}
+
+impl Ports for MousePorts {
+    const BASE: u8 = 0x90;
+}
+
+impl MousePorts {
+    const VECTOR: u8 = 0x00;
+    const X: u8 = 0x02;
+    const Y: u8 = 0x04;
+    const STATE: u8 = 0x06;
+    const SCROLL_X: u8 = 0x0a;
+    const SCROLL_Y: u8 = 0x0c;
+
+    pub const DESCRIPTION: DeviceDescription = DeviceDescription {
+        name: "Mouse",
+        base: Self::BASE,
+        ports: &[
+            PortDescription::short("vector", Self::VECTOR),
+            PortDescription::short("x", Self::X),
+            PortDescription::short("y", Self::Y),
+            PortDescription::byte("state", Self::STATE),
+            PortDescription::short("scrollx", Self::SCROLL_X),
+            PortDescription::short("scrolly", Self::SCROLL_Y),
+        ],
+    };
+}
+
+/// The buttons of the mouse, as bits of the `state` port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MouseButton {
+    Left = 0x01,
+    Middle = 0x02,
+    Right = 0x04,
+}
+
+#[derive(Debug)]
+pub struct MouseDevice {
+    vector: u16,
+}
+
+impl MouseDevice {
+    pub fn new() -> Self {
+        Self { vector: 0 }
+    }
+
+    pub fn vector(&self) -> u16 {
+        self.vector
+    }
+
+    /// Sets the pointer coordinates, and returns whether they changed
+    pub fn set_position(&mut self, cpu: &mut Cpu, x: u16, y: u16) -> bool {
+        let ports = cpu.device_page::<MousePorts>();
+        let has_changed =
+            peek_short(ports, MousePorts::X) != x || peek_short(ports, MousePorts::Y) != y;
+        poke_short(ports, MousePorts::X, x);
+        poke_short(ports, MousePorts::Y, y);
+
+        has_changed
+    }
+
+    /// Sets the state of every button, and returns whether it changed
+    pub fn set_buttons(&mut self, cpu: &mut Cpu, buttons: u8) -> bool {
+        let ports = cpu.device_page::<MousePorts>();
+        let has_changed = ports[MousePorts::STATE as usize] != buttons;
+        ports[MousePorts::STATE as usize] = buttons;
+
+        has_changed
+    }
+
+    pub fn set_scroll(&mut self, cpu: &mut Cpu, x: i16, y: i16) {
+        let ports = cpu.device_page::<MousePorts>();
+        poke_short(ports, MousePorts::SCROLL_X, x as u16);
+        poke_short(ports, MousePorts::SCROLL_Y, y as u16);
+    }
+
+    #[inline]
+    fn deo_vector(&mut self, cpu: &mut Cpu) {
+        let ports = cpu.device_page::<MousePorts>();
+        self.vector = peek_short(ports, MousePorts::VECTOR);
+    }
+}
+
+impl Device for MouseDevice {
+    fn deo(&mut self, cpu: &mut Cpu, _: u8, target: u8) {
+        if target == MousePorts::VECTOR {
+            self.deo_vector(cpu);
+        }
+    }
+}
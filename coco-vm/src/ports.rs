@@ -0,0 +1,107 @@
+use std::fmt;
+
+use crate::audio::AudioPorts;
+use crate::console::ConsolePorts;
+use crate::controller::ControllerPorts;
+use crate::datetime::DatetimePorts;
+use crate::file::FilePorts;
+use crate::mouse::MousePorts;
+use crate::system::SystemPorts;
+use crate::video::VideoPorts;
+
+/// A port of a device, as named in Tal sources.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortDescription {
+    pub name: &'static str,
+    /// Offset of the port within its device page
+    pub offset: u8,
+    /// Size of the port in bytes (2 for ports that hold a short)
+    pub size: u8,
+}
+
+impl PortDescription {
+    pub const fn byte(name: &'static str, offset: u8) -> Self {
+        Self {
+            name,
+            offset,
+            size: 1,
+        }
+    }
+
+    pub const fn short(name: &'static str, offset: u8) -> Self {
+        Self {
+            name,
+            offset,
+            size: 2,
+        }
+    }
+}
+
+/// The name, address and ports of a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceDescription {
+    pub name: &'static str,
+    /// Address of the device page
+    pub base: u8,
+    /// The ports of the device, sorted by offset
+    pub ports: &'static [PortDescription],
+}
+
+/// Displays the device as a line of Tal, e.g.
+/// `|10 @Screen &vector $2 &x $1 &y $1`
+impl fmt::Display for DeviceDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "|{:02x} @{}", self.base, self.name)?;
+
+        let mut offset = 0;
+        for port in self.ports {
+            if port.offset > offset {
+                write!(f, " ${:x}", port.offset - offset)?;
+            }
+            write!(f, " &{} ${:x}", port.name, port.size)?;
+            offset = port.offset + port.size;
+        }
+
+        Ok(())
+    }
+}
+
+/// Every device in the VM, sorted by address
+pub const DEVICES: &[DeviceDescription] = &[
+    SystemPorts::DESCRIPTION,
+    VideoPorts::DESCRIPTION,
+    ConsolePorts::DESCRIPTION,
+    AudioPorts::<0>::DESCRIPTION,
+    AudioPorts::<1>::DESCRIPTION,
+    AudioPorts::<2>::DESCRIPTION,
+    AudioPorts::<3>::DESCRIPTION,
+    ControllerPorts::DESCRIPTION,
+    MousePorts::DESCRIPTION,
+    FilePorts::DESCRIPTION,
+    DatetimePorts::DESCRIPTION,
+];
+
+/// Returns a Tal source that defines labels for every device port, so ROMs
+/// can use them (e.g. `.Screen/x DEO`) instead of hard-coding addresses
+pub fn tal_header() -> String {
+    let mut header =
+        "( COCO-8 devices. Generated by `coco-asm --header`, do not edit. )\n\n".to_string();
+    for device in DEVICES {
+        header += &format!("{}\n", device);
+    }
+    header
+}
+
+/// Returns the short held by the port at `offset` of a device page
+#[inline]
+pub(crate) fn peek_short(ports: &[u8], offset: u8) -> u16 {
+    u16::from_be_bytes([ports[offset as usize], ports[offset as usize + 1]])
+}
+
+/// Writes a short into the port at `offset` of a device page
+#[inline]
+pub(crate) fn poke_short(ports: &mut [u8], offset: u8, value: u16) {
+    let [hi, lo] = value.to_be_bytes();
+    ports[offset as usize] = hi;
+    ports[offset as usize + 1] = lo;
+}
@@ -0,0 +1,170 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+
+/// Where the File device keeps its files. Names are the ones given by the
+/// ROM, like `level1.bin` or `saves/slot1`.
+pub trait Storage: fmt::Debug {
+    /// Returns the whole contents of a file
+    fn read(&mut self, name: &str) -> io::Result<Vec<u8>>;
+    /// Writes data to a file, creating it if needed. Without `append`, the
+    /// previous contents are discarded.
+    fn write(&mut self, name: &str, data: &[u8], append: bool) -> io::Result<()>;
+    fn delete(&mut self, name: &str) -> io::Result<()>;
+    /// Returns the size of a file in bytes
+    fn size(&mut self, name: &str) -> io::Result<u64>;
+}
+
+/// Keeps files in a directory of the host. Names that would reach outside of
+/// it (absolute paths, `..`, or symlinks to somewhere else) are rejected.
+#[derive(Debug, Clone)]
+pub struct DirectoryStorage {
+    root: PathBuf,
+}
+
+impl DirectoryStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Returns where the file is on the host, with any symlinks resolved
+    fn path(&self, name: &str) -> io::Result<PathBuf> {
+        let path = Path::new(name);
+        let is_inside = path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        if name.is_empty() || !is_inside {
+            return Err(self.outside(name));
+        }
+
+        let root = fs::canonicalize(&self.root)?;
+        let path = root.join(path);
+        // files that don't exist yet can't be resolved, but the directory
+        // they'd be created in can
+        let path = match fs::symlink_metadata(&path) {
+            Ok(_) => fs::canonicalize(&path)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let parent = fs::canonicalize(path.parent().unwrap_or(&root))?;
+                parent.join(path.file_name().unwrap_or_default())
+            }
+            Err(err) => return Err(err),
+        };
+
+        match path.starts_with(&root) {
+            true => Ok(path),
+            false => Err(self.outside(name)),
+        }
+    }
+
+    fn outside(&self, name: &str) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is outside of {}", name, self.root.display()),
+        )
+    }
+}
+
+impl Storage for DirectoryStorage {
+    fn read(&mut self, name: &str) -> io::Result<Vec<u8>> {
+        fs::read(self.path(name)?)
+    }
+
+    fn write(&mut self, name: &str, data: &[u8], append: bool) -> io::Result<()> {
+        OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .open(self.path(name)?)?
+            .write_all(data)
+    }
+
+    fn delete(&mut self, name: &str) -> io::Result<()> {
+        fs::remove_file(self.path(name)?)
+    }
+
+    fn size(&mut self, name: &str) -> io::Result<u64> {
+        let metadata = fs::metadata(self.path(name)?)?;
+        match metadata.is_file() {
+            true => Ok(metadata.len()),
+            false => Err(io::Error::other("not a file")),
+        }
+    }
+}
+
+/// Keeps files in memory, for hosts without a filesystem and for tests.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStorage {
+    files: HashMap<String, Vec<u8>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.files.get(name).map(Vec::as_slice)
+    }
+
+    pub fn insert(&mut self, name: &str, data: &[u8]) {
+        self.files.insert(name.to_string(), data.to_vec());
+    }
+
+    fn not_found(name: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::NotFound, format!("{} not found", name))
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn read(&mut self, name: &str) -> io::Result<Vec<u8>> {
+        self.files.get(name).cloned().ok_or(Self::not_found(name))
+    }
+
+    fn write(&mut self, name: &str, data: &[u8], append: bool) -> io::Result<()> {
+        let file = self.files.entry(name.to_string()).or_default();
+        if !append {
+            file.clear();
+        }
+        file.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn delete(&mut self, name: &str) -> io::Result<()> {
+        self.files
+            .remove(name)
+            .map(|_| ())
+            .ok_or(Self::not_found(name))
+    }
+
+    fn size(&mut self, name: &str) -> io::Result<u64> {
+        self.files
+            .get(name)
+            .map(|file| file.len() as u64)
+            .ok_or(Self::not_found(name))
+    }
+}
+
+/// Shares a storage with the host, so it can look at the files after handing
+/// the storage over to the VM.
+impl<S: Storage> Storage for Rc<RefCell<S>> {
+    fn read(&mut self, name: &str) -> io::Result<Vec<u8>> {
+        self.borrow_mut().read(name)
+    }
+
+    fn write(&mut self, name: &str, data: &[u8], append: bool) -> io::Result<()> {
+        self.borrow_mut().write(name, data, append)
+    }
+
+    fn delete(&mut self, name: &str) -> io::Result<()> {
+        self.borrow_mut().delete(name)
+    }
+
+    fn size(&mut self, name: &str) -> io::Result<u64> {
+        self.borrow_mut().size(name)
+    }
+}
@@ -1,4 +1,9 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
 use super::Device;
+use crate::ports::poke_short;
+use crate::{DeviceDescription, PortDescription};
 use coco_core::Ports;
 
 #[derive(Debug)]
@@ -11,18 +16,78 @@ impl Ports for SystemPorts {
 impl SystemPorts {
     const VECTOR: u8 = 0x00;
     const DEBUG: u8 = 0x02;
+    const RANDOM: u8 = 0x04;
+    const STATE: u8 = 0x0f;
+
+    pub const DESCRIPTION: DeviceDescription = DeviceDescription {
+        name: "System",
+        base: Self::BASE,
+        ports: &[
+            PortDescription::short("vector", Self::VECTOR),
+            PortDescription::byte("debug", Self::DEBUG),
+            PortDescription::short("random", Self::RANDOM),
+            PortDescription::byte("state", Self::STATE),
+        ],
+    };
 }
 
 #[derive(Debug)]
 pub struct SystemDevice {
     stdout: String,
+    vector: u16,
+    /// State of the xorshift generator behind the `random` port. Never zero.
+    seed: u16,
+    /// Exit code the ROM has halted with, if it has
+    state: Option<u8>,
 }
 
 impl SystemDevice {
     pub fn new() -> Self {
-        Self {
+        // the keys of the std hasher come from the OS, so they make a seed
+        // without any extra dependency. They're fixed on targets without an
+        // OS RNG (like wasm32-unknown-unknown), so hosts there should seed
+        // it with `Vm::with_seed`.
+        let mut device = Self {
             stdout: "".to_string(),
-        }
+            vector: 0,
+            seed: 0x0001,
+            state: None,
+        };
+        device.set_seed(RandomState::new().hash_one(0x00) as u16);
+        device
+    }
+
+    /// Seeds the generator behind the `random` port. A zero seed, which
+    /// would get the generator stuck, is replaced by `1`.
+    pub fn set_seed(&mut self, seed: u16) {
+        self.seed = seed.max(0x0001);
+    }
+
+    /// Returns the address of the fault handler, or zero if the ROM
+    /// has not set any
+    pub fn vector(&self) -> u16 {
+        self.vector
+    }
+
+    /// Returns whether the ROM has asked to stop, by writing to the `state`
+    /// port
+    pub fn is_halted(&self) -> bool {
+        self.state.is_some()
+    }
+
+    /// Returns the exit code the ROM has halted with: the value written to
+    /// the `state` port, without its top bit. Zero if it hasn't halted.
+    pub fn exit_code(&self) -> u8 {
+        self.state.unwrap_or(0x00)
+    }
+
+    #[inline]
+    fn deo_vector(&mut self, cpu: &mut coco_core::Cpu) {
+        let ports = cpu.device_page::<SystemPorts>();
+        let hi = ports[SystemPorts::VECTOR as usize];
+        let lo = ports[SystemPorts::VECTOR as usize + 1];
+
+        self.vector = u16::from_be_bytes([hi, lo]);
     }
 
     pub fn debug(&mut self, cpu: &mut coco_core::Cpu) {
@@ -38,6 +103,20 @@ impl SystemDevice {
         self.stdout += &format!("{}", cpu);
     }
 
+    /// Moves the xorshift generator forward and puts its value in the
+    /// `random` port
+    fn dei_random(&mut self, cpu: &mut coco_core::Cpu) {
+        self.seed ^= self.seed << 7;
+        self.seed ^= self.seed >> 9;
+        self.seed ^= self.seed << 8;
+
+        poke_short(
+            cpu.device_page::<SystemPorts>(),
+            SystemPorts::RANDOM,
+            self.seed,
+        );
+    }
+
     /// Returns the stdout buffer and flushes it
     pub fn stdout(&mut self) -> String {
         let res = self.stdout.to_owned();
@@ -47,13 +126,23 @@ impl SystemDevice {
 }
 
 impl Device for SystemDevice {
-    fn deo(&mut self, cpu: &mut coco_core::Cpu, target: u8) {
+    fn deo(&mut self, cpu: &mut coco_core::Cpu, _: u8, target: u8) {
         match target {
-            SystemPorts::VECTOR => {}
+            SystemPorts::VECTOR => self.deo_vector(cpu),
             SystemPorts::DEBUG => self.debug(cpu),
+            SystemPorts::STATE => {
+                let value = cpu.device_page::<SystemPorts>()[target as usize];
+                if value != 0x00 && self.state.is_none() {
+                    self.state = Some(value & 0x7f);
+                }
+            }
             _ => {}
         }
     }
 
-    fn dei(&mut self, _: &mut coco_core::Cpu, _: u8) {}
+    fn dei(&mut self, cpu: &mut coco_core::Cpu, _: u8, target: u8) {
+        if target == SystemPorts::RANDOM {
+            self.dei_random(cpu);
+        }
+    }
 }
@@ -1,6 +1,7 @@
 use core::cmp;
 
 use super::Device;
+use crate::{DeviceDescription, PortDescription};
 use coco_core::{Cpu, Ports};
 
 #[derive(Debug)]
@@ -15,8 +16,25 @@ impl VideoPorts {
     const X: u8 = 0x02;
     const Y: u8 = 0x03;
     const PIXEL: u8 = 0x04;
+    const WIDTH: u8 = 0x05;
+    const HEIGHT: u8 = 0x06;
     const ADDRESS: u8 = 0x08;
     const SPRITE: u8 = 0x0a;
+
+    pub const DESCRIPTION: DeviceDescription = DeviceDescription {
+        name: "Screen",
+        base: Self::BASE,
+        ports: &[
+            PortDescription::short("vector", Self::VECTOR),
+            PortDescription::byte("x", Self::X),
+            PortDescription::byte("y", Self::Y),
+            PortDescription::byte("pixel", Self::PIXEL),
+            PortDescription::byte("width", Self::WIDTH),
+            PortDescription::byte("height", Self::HEIGHT),
+            PortDescription::short("address", Self::ADDRESS),
+            PortDescription::byte("sprite", Self::SPRITE),
+        ],
+    };
 }
 
 pub const SCREEN_WIDTH: u8 = 192;
@@ -25,6 +43,27 @@ pub const VIDEO_BUFFER_LEN: usize = SCREEN_WIDTH as usize * SCREEN_HEIGHT as usi
 
 pub type Pixel = u8;
 pub type VideoBuffer = [Pixel; VIDEO_BUFFER_LEN];
+pub type Rgb = (u8, u8, u8);
+
+/// The 16-color COCO-8 palette, indexed by pixel value
+pub const THEME: [Rgb; 0x10] = [
+    (0x00, 0x00, 0x00),
+    (0x1D, 0x2B, 0x53),
+    (0x7E, 0x25, 0x53),
+    (0x00, 0x87, 0x51),
+    (0xAB, 0x52, 0x36),
+    (0x5F, 0x57, 0x4F),
+    (0xC2, 0xC3, 0xC7),
+    (0xFF, 0xF1, 0xE8),
+    (0xFF, 0x00, 0x4D),
+    (0xFF, 0xA3, 0x00),
+    (0xFF, 0xEC, 0x27),
+    (0x00, 0xE4, 0x36),
+    (0x29, 0xAD, 0xFF),
+    (0x83, 0x76, 0x9C),
+    (0xFF, 0x77, 0xA8),
+    (0xFF, 0xCC, 0xAA),
+];
 
 #[derive(Debug)]
 pub struct VideoDevice {
@@ -53,7 +92,7 @@ impl VideoDevice {
             self.refresh_buffer();
         }
 
-        return &self.buffer;
+        &self.buffer
     }
 
     fn refresh_buffer(&mut self) {
@@ -68,8 +107,8 @@ impl VideoDevice {
 
     #[inline]
     fn xy(&self, ports: &[u8]) -> (u8, u8) {
-        let x = cmp::min(ports[VideoPorts::X as usize], (SCREEN_WIDTH - 1) as u8);
-        let y = cmp::min(ports[VideoPorts::Y as usize], (SCREEN_HEIGHT - 1) as u8);
+        let x = cmp::min(ports[VideoPorts::X as usize], SCREEN_WIDTH - 1);
+        let y = cmp::min(ports[VideoPorts::Y as usize], SCREEN_HEIGHT - 1);
         (x, y)
     }
 
@@ -156,10 +195,10 @@ impl VideoDevice {
     fn sprite_data(&self, base_addr: u16, cpu: &Cpu) -> [Pixel; 64] {
         let mut addr = base_addr;
         let mut res = [0x00; 64];
-        for row in 0..8 as usize {
-            for chunk in 0..4 as usize {
+        for row in 0..8_usize {
+            for chunk in 0..4_usize {
                 let pixel_data = cpu.ram_peek_byte(addr.wrapping_add(chunk as u16));
-                res[row * 8 + chunk * 2 + 0] = (0b1111_0000 & pixel_data) >> 4;
+                res[row * 8 + chunk * 2] = (0b1111_0000 & pixel_data) >> 4;
                 res[row * 8 + chunk * 2 + 1] = 0b0000_1111 & pixel_data;
             }
             addr = addr.wrapping_add(4);
@@ -174,7 +213,7 @@ impl VideoDevice {
 }
 
 impl Device for VideoDevice {
-    fn deo(&mut self, cpu: &mut Cpu, target: u8) {
+    fn deo(&mut self, cpu: &mut Cpu, _: u8, target: u8) {
         match target {
             VideoPorts::VECTOR => self.deo_vector(cpu),
             VideoPorts::X => {}
@@ -188,5 +227,12 @@ impl Device for VideoDevice {
         }
     }
 
-    fn dei(&mut self, _: &mut Cpu, _: u8) {}
+    fn dei(&mut self, cpu: &mut Cpu, _: u8, target: u8) {
+        // both are set at once, so they can be read together with DEI2
+        if let VideoPorts::WIDTH | VideoPorts::HEIGHT = target {
+            let ports = cpu.device_page::<VideoPorts>();
+            ports[VideoPorts::WIDTH as usize] = SCREEN_WIDTH;
+            ports[VideoPorts::HEIGHT as usize] = SCREEN_HEIGHT;
+        }
+    }
 }
@@ -0,0 +1,237 @@
+use coco_core::opcodes::*;
+use coco_core::Cpu;
+use coco_vm::{Vm, SAMPLE_RATE};
+
+const VECTOR_OUTPUT: &str = "WRK: []\nRET: []";
+
+/// A ROM that plays `sample` in the first audio channel. Its vector outputs
+/// debug info, so tests can tell when it's called.
+fn note_rom(adsr: u16, volume: u8, pitch: u8, sample: &[u8]) -> Vec<u8> {
+    voice_rom(0x00, adsr, volume, pitch, sample)
+}
+
+/// Like `note_rom`, but selecting a voice with the `wave` port
+fn voice_rom(wave: u8, adsr: u16, volume: u8, pitch: u8, sample: &[u8]) -> Vec<u8> {
+    const CODE_LEN: u16 = 40;
+    const VECTOR_LEN: u16 = 6;
+    let vector = 0x100 + CODE_LEN;
+    let address = vector + VECTOR_LEN;
+
+    let mut rom = vec![];
+    for (value, port) in [
+        (vector, 0x30),
+        (adsr, 0x38),
+        (sample.len() as u16, 0x3a),
+        (address, 0x3c),
+    ] {
+        let [hi, lo] = value.to_be_bytes();
+        rom.extend_from_slice(&[PUSH2, hi, lo, PUSH, port, DEO2]);
+    }
+    rom.extend_from_slice(&[PUSH, wave, PUSH, 0x35, DEO]);
+    rom.extend_from_slice(&[PUSH, volume, PUSH, 0x3e, DEO]);
+    rom.extend_from_slice(&[PUSH, pitch, PUSH, 0x3f, DEO]);
+    rom.push(BRK);
+    rom.extend_from_slice(&[PUSH, 0x01, PUSH, 0x02, DEO, BRK]);
+    rom.extend_from_slice(sample);
+
+    rom
+}
+
+/// Returns the left and right sides of a stereo buffer
+fn sides(buffer: &[i16]) -> (Vec<i16>, Vec<i16>) {
+    buffer
+        .chunks_exact(2)
+        .map(|frame| (frame[0], frame[1]))
+        .unzip()
+}
+
+#[test]
+fn test_audio_is_silent_without_notes() {
+    let rom = [BRK];
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new();
+    vm.on_reset(&mut cpu).unwrap();
+
+    let mut buffer = [0x7f; 0x100];
+    let output = vm.on_audio(&mut cpu, &mut buffer).unwrap();
+
+    assert!(buffer.iter().all(|&x| x == 0));
+    assert_eq!(output.sys_stdout, "".to_string());
+}
+
+#[test]
+fn test_audio_plays_sample_at_middle_c() {
+    let sample: Vec<u8> = (0..300).map(|i| i as u8).collect();
+    let rom = note_rom(0x0000, 0xf0, 0x80 | 60, &sample);
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new();
+    vm.on_reset(&mut cpu).unwrap();
+
+    let mut start = [0; 200];
+    let output = vm.on_audio(&mut cpu, &mut start).unwrap();
+    assert_eq!(output.sys_stdout, "".to_string());
+    assert_eq!(cpu.device_peek_byte(0x33), 100); // position
+    assert_eq!(cpu.device_peek_byte(0x34), 0xff); // output
+
+    let mut rest = [0; 400];
+    let output = vm.on_audio(&mut cpu, &mut rest).unwrap();
+    assert_eq!(output.sys_stdout, VECTOR_OUTPUT.to_string());
+
+    let (left, right) = sides(&[start.as_slice(), rest.as_slice()].concat());
+    let expected: Vec<i16> = sample
+        .iter()
+        .map(|&x| (((x as i32 - 0x80) * 0x100 * 0xf) >> 6) as i16)
+        .collect();
+    assert_eq!(left[..300], expected);
+    assert!(left[300..].iter().all(|&x| x == 0));
+    assert!(right.iter().all(|&x| x == 0));
+}
+
+#[test]
+fn test_audio_loops_single_cycle_sample() {
+    // a square wave, played as A4 (440 Hz)
+    let rom = note_rom(0x0000, 0x0f, 69, &[0xff, 0x00]);
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new();
+    vm.on_reset(&mut cpu).unwrap();
+
+    let mut buffer = vec![0; SAMPLE_RATE as usize * 2];
+    let output = vm.on_audio(&mut cpu, &mut buffer).unwrap();
+
+    let (left, right) = sides(&buffer);
+    let cycles = right.windows(2).filter(|x| x[0] > 0 && x[1] < 0).count();
+    assert!((439..=441).contains(&cycles), "got {} cycles", cycles);
+    assert!(left.iter().all(|&x| x == 0));
+    assert_eq!(output.sys_stdout, "".to_string());
+}
+
+#[test]
+fn test_audio_releases_envelope() {
+    // only a release of one step, which starts at half volume
+    let rom = note_rom(0x0001, 0xff, 60, &[0xff; 0x10]);
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new();
+    vm.on_reset(&mut cpu).unwrap();
+
+    let frames = SAMPLE_RATE as usize / 15;
+    let mut buffer = vec![0; (frames + 10) * 2];
+    let output = vm.on_audio(&mut cpu, &mut buffer).unwrap();
+
+    let (left, right) = sides(&buffer);
+    assert_eq!(left[0], ((0x7f * 0x80 * 0xf) >> 6) as i16);
+    assert!(left[frames / 2] < left[0]);
+    assert!(left[frames..].iter().all(|&x| x == 0));
+    assert_eq!(left, right);
+    assert_eq!(output.sys_stdout, VECTOR_OUTPUT.to_string());
+}
+
+#[test]
+fn test_audio_stops_without_length() {
+    let rom = note_rom(0x0000, 0xff, 60, &[]);
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new();
+    vm.on_reset(&mut cpu).unwrap();
+
+    let mut buffer = [0; 0x100];
+    let output = vm.on_audio(&mut cpu, &mut buffer).unwrap();
+
+    assert!(buffer.iter().all(|&x| x == 0));
+    assert_eq!(output.sys_stdout, "".to_string());
+}
+
+/// Returns the left side of the first frames of a voice, at a pitch high
+/// enough to go through a few periods
+fn voice_snapshot(wave: u8, frames: usize) -> Vec<i16> {
+    let rom = voice_rom(wave, 0x0000, 0x11, 0x80 | 105, &[]);
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new();
+    vm.on_reset(&mut cpu).unwrap();
+
+    let mut buffer = vec![0; frames * 2];
+    let output = vm.on_audio(&mut cpu, &mut buffer).unwrap();
+    assert_eq!(output.sys_stdout, "".to_string(), "voices don't end");
+
+    sides(&buffer).0
+}
+
+#[test]
+fn test_audio_square_voice_duty() {
+    // a 25% duty cycle, played as A4 (440 Hz)
+    let rom = voice_rom(0x41, 0x0000, 0xf0, 69, &[]);
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new();
+    vm.on_reset(&mut cpu).unwrap();
+
+    let mut buffer = vec![0; SAMPLE_RATE as usize * 2];
+    vm.on_audio(&mut cpu, &mut buffer).unwrap();
+
+    let (left, _) = sides(&buffer);
+    let high = left.iter().filter(|&&x| x > 0).count();
+    let cycles = left.windows(2).filter(|x| x[0] > 0 && x[1] < 0).count();
+    assert!((439..=441).contains(&cycles), "got {} cycles", cycles);
+    assert!(
+        (SAMPLE_RATE as usize / 4).abs_diff(high) < 100,
+        "high for {} frames",
+        high
+    );
+}
+
+#[test]
+fn test_audio_voices_snapshot() {
+    let square = voice_snapshot(0x01, 32);
+    let triangle = voice_snapshot(0x02, 32);
+    let saw = voice_snapshot(0x03, 32);
+    let noise = voice_snapshot(0x04, 32);
+
+    assert_eq!(
+        square,
+        [
+            508, 508, 508, 508, 508, 508, 508, -512, -512, -512, -512, -512, -512, 508, 508, 508,
+            508, 508, 508, -512, -512, -512, -512, -512, -512, -512, 508, 508, 508, 508, 508, 508
+        ]
+    );
+    assert_eq!(
+        triangle,
+        [
+            -512, -352, -192, -24, 136, 304, 464, 384, 224, 64, -104, -264, -432, -440, -272, -112,
+            48, 216, 376, 472, 312, 144, -16, -176, -344, -504, -360, -200, -32, 128, 296, 456
+        ]
+    );
+    assert_eq!(
+        saw,
+        [
+            -512, -432, -352, -268, -188, -104, -24, 60, 140, 220, 304, 384, 468, -476, -392, -312,
+            -232, -148, -68, 16, 96, 180, 260, 340, 424, 504, -436, -356, -272, -192, -108, -28
+        ]
+    );
+    assert_eq!(
+        noise,
+        [
+            508, 508, 508, 508, 508, 508, 508, 508, 508, 508, 508, 508, 508, 508, 508, -512, -512,
+            -512, -512, -512, -512, -512, -512, -512, -512, -512, -512, -512, -512, 508, -512,
+            -512
+        ]
+    );
+}
+
+#[test]
+fn test_audio_noise_voice_is_deterministic() {
+    let noise = voice_snapshot(0x04, 0x1000);
+
+    assert_eq!(noise, voice_snapshot(0x04, 0x1000));
+    assert!(noise.contains(&((0x7f * 0x100) >> 6)));
+    assert!(noise.contains(&((-0x80 * 0x100) >> 6)));
+}
+
+#[test]
+fn test_audio_stops_above_highest_note() {
+    let rom = voice_rom(0x01, 0x0000, 0xff, 108, &[]);
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new();
+    vm.on_reset(&mut cpu).unwrap();
+
+    let mut buffer = [0; 0x100];
+    vm.on_audio(&mut cpu, &mut buffer).unwrap();
+
+    assert!(buffer.iter().all(|&x| x == 0));
+}
@@ -0,0 +1,136 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use coco_core::opcodes::*;
+use coco_core::Cpu;
+use coco_vm::{yearday, Clock, DateTime, FixedClock, Vm};
+
+/// A clock that moves one second forward every time it's read
+#[derive(Debug)]
+struct TickingClock {
+    seconds: Rc<Cell<i64>>,
+}
+
+impl Clock for TickingClock {
+    fn now(&mut self) -> DateTime {
+        self.seconds.set(self.seconds.get() + 1);
+        DateTime::from_unix_seconds(self.seconds.get())
+    }
+}
+
+fn datetime_ports(cpu: &Cpu) -> Vec<u8> {
+    (0xc0..=0xca)
+        .map(|port| cpu.device_peek_byte(port))
+        .collect()
+}
+
+#[test]
+fn test_datetime_from_unix_seconds() {
+    let epoch = DateTime::from_unix_seconds(0);
+    let leap_day = DateTime::from_unix_seconds(951_782_400);
+    let new_years_eve = DateTime::from_unix_seconds(1_735_689_599);
+
+    assert_eq!(
+        epoch,
+        DateTime {
+            year: 1970,
+            month: 0,
+            day: 1,
+            weekday: 4,
+            ..DateTime::default()
+        }
+    );
+    assert_eq!(
+        leap_day,
+        DateTime {
+            year: 2000,
+            month: 1,
+            day: 29,
+            weekday: 2,
+            yearday: 59,
+            ..DateTime::default()
+        }
+    );
+    assert_eq!(
+        new_years_eve,
+        DateTime {
+            year: 2024,
+            month: 11,
+            day: 31,
+            hour: 23,
+            minute: 59,
+            second: 59,
+            weekday: 2,
+            yearday: 365,
+            is_dst: false,
+        }
+    );
+}
+
+#[test]
+fn test_yearday() {
+    assert_eq!(yearday(2023, 0, 1), 0);
+    assert_eq!(yearday(2023, 2, 1), 59);
+    assert_eq!(yearday(2024, 2, 1), 60);
+    assert_eq!(yearday(1900, 2, 1), 59);
+}
+
+#[test]
+fn test_yearday_of_day_zero() {
+    assert_eq!(yearday(2023, 0, 0), 0);
+    assert_eq!(yearday(2023, 2, 0), 59);
+}
+
+#[test]
+fn test_default_datetime_ports() {
+    let rom = [PUSH, 0xc0, DEI2, BRK];
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new().with_clock(FixedClock(DateTime::default()));
+
+    vm.on_reset(&mut cpu).unwrap();
+
+    assert_eq!(cpu.working_stack(), [0x00, 0x00]);
+    assert_eq!(datetime_ports(&cpu), [0x00; 11]);
+}
+
+#[test]
+fn test_datetime_ports() {
+    let rom = [PUSH, 0xc0, DEI2, BRK];
+    let mut cpu = Cpu::new(&rom);
+    let clock = FixedClock(DateTime {
+        year: 2024,
+        month: 6,
+        day: 18,
+        hour: 9,
+        minute: 30,
+        second: 5,
+        weekday: 4,
+        yearday: 199,
+        is_dst: true,
+    });
+    let mut vm = Vm::new().with_clock(clock);
+
+    vm.on_reset(&mut cpu).unwrap();
+
+    assert_eq!(cpu.working_stack(), [0x07, 0xe8]);
+    assert_eq!(
+        datetime_ports(&cpu),
+        [0x07, 0xe8, 6, 18, 9, 30, 5, 4, 0x00, 199, 0x01]
+    );
+}
+
+#[test]
+fn test_datetime_ports_are_refreshed_on_every_read() {
+    let rom = [PUSH, 0xc6, DEI, PUSH, 0xc6, DEI, BRK];
+    let mut cpu = Cpu::new(&rom);
+    let seconds = Rc::new(Cell::new(0));
+    let clock = TickingClock {
+        seconds: seconds.clone(),
+    };
+    let mut vm = Vm::new().with_clock(clock);
+
+    vm.on_reset(&mut cpu).unwrap();
+
+    assert_eq!(cpu.working_stack(), [1, 2]);
+    assert_eq!(seconds.get(), 2);
+}
@@ -0,0 +1,191 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use coco_core::opcodes::*;
+use coco_core::{Cpu, Fault};
+use coco_vm::{Device, DeviceRegistry, Vm};
+
+/// A device that records what the ROM writes to it, and puts a reading in
+/// port `0x02` when port `0x01` is written to
+#[derive(Debug, Default)]
+struct Sensor {
+    writes: Vec<(u8, u8)>,
+}
+
+impl Device for Sensor {
+    fn deo(&mut self, cpu: &mut Cpu, base: u8, target: u8) {
+        let ports = cpu.device_page_at(base);
+        self.writes.push((target, ports[target as usize]));
+        if target == 0x01 {
+            ports[0x02] = 0x2a;
+        }
+    }
+}
+
+#[test]
+fn test_custom_device_receives_deo() {
+    let rom = [
+        PUSH, 0xab, PUSH, 0xe0, DEO, // write to port 0
+        PUSH2, 0x12, 0x34, PUSH, 0xe4, DEO2, // write a short to port 4
+        BRK,
+    ];
+    let mut cpu = Cpu::new(&rom);
+    let sensor = Rc::new(RefCell::new(Sensor::default()));
+    let mut vm = Vm::new().with_device(0xe0, sensor.clone());
+
+    vm.on_reset(&mut cpu).unwrap();
+
+    assert_eq!(sensor.borrow().writes, vec![(0x00, 0xab), (0x04, 0x12)]);
+    assert_eq!(cpu.device_peek_byte(0xe4), 0x12);
+    assert_eq!(cpu.device_peek_byte(0xe5), 0x34);
+}
+
+#[test]
+fn test_custom_device_can_write_its_ports() {
+    let rom = [PUSH, 0x01, PUSH, 0xe1, DEO, PUSH, 0xe2, DEI, BRK];
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new().with_device(0xe0, Sensor::default());
+
+    vm.on_reset(&mut cpu).unwrap();
+
+    assert_eq!(cpu.working_stack(), [0x2a]);
+}
+
+#[test]
+fn test_unplugged_device_faults() {
+    let rom = [PUSH, 0xab, PUSH, 0xf0, DEO, BRK];
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new().with_device(0xe0, Sensor::default());
+
+    let trap = vm.on_reset(&mut cpu).unwrap_err();
+
+    assert_eq!(trap.fault, Fault::UnknownDevice(0xf0));
+}
+
+#[test]
+fn test_remove_device() {
+    let rom = [PUSH, 0xab, PUSH, 0xe0, DEO, BRK];
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new().with_device(0xe0, Sensor::default());
+
+    assert!(vm.remove_device(0xe0).is_some());
+    let trap = vm.on_reset(&mut cpu).unwrap_err();
+
+    assert_eq!(trap.fault, Fault::UnknownDevice(0xe0));
+}
+
+#[test]
+fn test_same_device_in_two_pages() {
+    let rom = [
+        PUSH, 0x01, PUSH, 0xe1, DEO, // write to port 1 of the first sensor
+        PUSH, 0x02, PUSH, 0x71, DEO, // write to port 1 of the second one
+        BRK,
+    ];
+    let mut cpu = Cpu::new(&rom);
+    let first = Rc::new(RefCell::new(Sensor::default()));
+    let second = Rc::new(RefCell::new(Sensor::default()));
+    let mut vm = Vm::new()
+        .with_device(0xe0, first.clone())
+        .with_device(0x70, second.clone());
+
+    vm.on_reset(&mut cpu).unwrap();
+
+    assert_eq!(first.borrow().writes, vec![(0x01, 0x01)]);
+    assert_eq!(second.borrow().writes, vec![(0x01, 0x02)]);
+    assert_eq!(cpu.device_peek_byte(0xe2), 0x2a);
+    assert_eq!(cpu.device_peek_byte(0x72), 0x2a);
+}
+
+#[test]
+fn test_same_device_in_two_pages_updates_its_own_ports() {
+    let rom = [PUSH, 0xe0, DEI, PUSH, 0xe0, DEI, PUSH, 0x70, DEI, BRK];
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new()
+        .with_device(0xe0, Counter::default())
+        .with_device(0x70, Counter::default());
+
+    vm.on_reset(&mut cpu).unwrap();
+
+    assert_eq!(cpu.working_stack(), [0x01, 0x02, 0x01]);
+}
+
+#[test]
+fn test_typed_access_to_devices() {
+    let mut vm = Vm::new().with_device(0xe0, Counter::default());
+
+    vm.device_mut::<Counter>(0xe0).unwrap().count = 5;
+
+    assert_eq!(vm.device::<Counter>(0xe0).unwrap().count, 5);
+    assert!(vm.device::<Sensor>(0xe0).is_none());
+    assert!(vm.device::<Counter>(0x70).is_none());
+}
+
+#[test]
+#[should_panic(expected = "device page 10 is used by a built-in device")]
+fn test_removing_builtin_device_panics() {
+    let mut vm = Vm::new();
+
+    vm.remove_device(0x10);
+}
+
+#[test]
+fn test_builtin_pages_are_reserved() {
+    assert!(DeviceRegistry::is_reserved(0x00));
+    assert!(DeviceRegistry::is_reserved(0x18));
+    assert!(DeviceRegistry::is_reserved(0xc0));
+    assert!(!DeviceRegistry::is_reserved(0x70));
+    assert!(!DeviceRegistry::is_reserved(0xe0));
+}
+
+#[test]
+#[should_panic(expected = "device page 10 is used by a built-in device")]
+fn test_plugging_into_builtin_page_panics() {
+    let _ = Vm::new().with_device(0x10, Sensor::default());
+}
+
+#[test]
+fn test_on_vector_runs_custom_vector() {
+    let rom = [
+        BRK, // reset
+        PUSH, 0x01, PUSH, 0xe1, DEO, BRK, // vector at 0x0101
+    ];
+    let mut cpu = Cpu::new(&rom);
+    let sensor = Rc::new(RefCell::new(Sensor::default()));
+    let mut vm = Vm::new().with_device(0xe0, sensor.clone());
+
+    vm.on_reset(&mut cpu).unwrap();
+    vm.on_vector(&mut cpu, 0x0000).unwrap();
+    assert!(sensor.borrow().writes.is_empty());
+    vm.on_vector(&mut cpu, 0x0101).unwrap();
+
+    assert_eq!(sensor.borrow().writes, vec![(0x01, 0x01)]);
+    assert_eq!(cpu.device_peek_byte(0xe2), 0x2a);
+}
+
+/// A device with a counter in port `0x00`, which goes up on every read
+#[derive(Debug, Default)]
+struct Counter {
+    count: u8,
+}
+
+impl Device for Counter {
+    fn dei(&mut self, cpu: &mut Cpu, base: u8, target: u8) {
+        if target == 0x00 {
+            self.count += 1;
+            cpu.device_page_at(base)[0x00] = self.count;
+        }
+    }
+
+    fn deo(&mut self, _: &mut Cpu, _: u8, _: u8) {}
+}
+
+#[test]
+fn test_custom_device_updates_ports_on_dei() {
+    let rom = [PUSH, 0xe0, DEI, PUSH, 0xe0, DEI, PUSH, 0xe0, DEI2, BRK];
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new().with_device(0xe0, Counter::default());
+
+    vm.on_reset(&mut cpu).unwrap();
+
+    assert_eq!(cpu.working_stack(), [0x01, 0x02, 0x03, 0x00]);
+}
@@ -0,0 +1,216 @@
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use coco_core::opcodes::*;
+use coco_core::Cpu;
+use coco_vm::{DirectoryStorage, MemoryStorage, Storage, Vm};
+
+/// Address of the name of the file, `a.txt`
+const NAME: u16 = 0x0200;
+/// Address of the data to write, `hello`
+const DATA: u16 = 0x0210;
+/// Address where files are read into
+const BUFFER: u16 = 0x0300;
+
+fn deo(value: u8, port: u8) -> Vec<u8> {
+    vec![PUSH, value, PUSH, port, DEO]
+}
+
+fn deo2(value: u16, port: u8) -> Vec<u8> {
+    let [hi, lo] = value.to_be_bytes();
+    vec![PUSH2, hi, lo, PUSH, port, DEO2]
+}
+
+/// A ROM that runs `code` on reset, with the name and data of a file in
+/// memory
+fn file_rom(code: &[Vec<u8>]) -> Vec<u8> {
+    let mut rom = code.concat();
+    rom.push(BRK);
+    rom.resize((NAME - 0x100) as usize, 0x00);
+    rom.extend_from_slice(b"a.txt\0");
+    rom.resize((DATA - 0x100) as usize, 0x00);
+    rom.extend_from_slice(b"hello");
+    rom
+}
+
+fn run(rom: &[u8], storage: impl Storage + 'static) -> Cpu {
+    let mut cpu = Cpu::new(rom);
+    let mut vm = Vm::new().with_storage(storage);
+    vm.on_reset(&mut cpu).unwrap();
+    cpu
+}
+
+fn success(cpu: &Cpu) -> u16 {
+    u16::from_be_bytes([cpu.device_peek_byte(0xa2), cpu.device_peek_byte(0xa3)])
+}
+
+fn ram(cpu: &Cpu, addr: u16, len: u16) -> Vec<u8> {
+    (0..len).map(|i| cpu.ram_peek_byte(addr + i)).collect()
+}
+
+#[test]
+fn test_file_write() {
+    let storage = Rc::new(RefCell::new(MemoryStorage::new()));
+    storage.borrow_mut().insert("a.txt", b"old contents");
+    let rom = file_rom(&[deo2(NAME, 0xa8), deo2(5, 0xaa), deo2(DATA, 0xae)]);
+
+    let cpu = run(&rom, storage.clone());
+
+    assert_eq!(success(&cpu), 5);
+    assert_eq!(storage.borrow().get("a.txt"), Some(b"hello".as_slice()));
+}
+
+#[test]
+fn test_file_append() {
+    let storage = Rc::new(RefCell::new(MemoryStorage::new()));
+    storage.borrow_mut().insert("a.txt", b"> ");
+    let rom = file_rom(&[
+        deo(0x01, 0xa7),
+        deo2(NAME, 0xa8),
+        deo2(5, 0xaa),
+        deo2(DATA, 0xae),
+        deo2(DATA, 0xae),
+    ]);
+
+    run(&rom, storage.clone());
+
+    assert_eq!(
+        storage.borrow().get("a.txt"),
+        Some(b"> hellohello".as_slice())
+    );
+}
+
+#[test]
+fn test_file_read_in_chunks() {
+    let mut storage = MemoryStorage::new();
+    storage.insert("a.txt", b"hello");
+    let rom = file_rom(&[
+        deo2(NAME, 0xa8),
+        deo2(2, 0xaa),
+        deo2(BUFFER, 0xac),
+        deo2(BUFFER + 2, 0xac),
+        deo2(BUFFER + 4, 0xac),
+    ]);
+
+    let cpu = run(&rom, storage);
+
+    assert_eq!(success(&cpu), 1);
+    assert_eq!(ram(&cpu, BUFFER, 6), b"hello\0");
+}
+
+#[test]
+fn test_file_read_after_write() {
+    let mut storage = MemoryStorage::new();
+    storage.insert("a.txt", b"old contents");
+    let rom = file_rom(&[
+        deo2(NAME, 0xa8),
+        deo2(3, 0xaa),
+        deo2(BUFFER, 0xac),
+        deo2(5, 0xaa),
+        deo2(DATA, 0xae),
+        deo2(BUFFER, 0xac),
+    ]);
+
+    let cpu = run(&rom, storage);
+
+    assert_eq!(success(&cpu), 5);
+    assert_eq!(ram(&cpu, BUFFER, 5), b"hello");
+}
+
+#[test]
+fn test_file_stat() {
+    let mut storage = MemoryStorage::new();
+    storage.insert("a.txt", &[0x00; 0x1f]);
+    let rom = file_rom(&[
+        deo2(NAME, 0xa8),
+        deo2(4, 0xaa),
+        deo2(BUFFER, 0xa4),
+        deo2(1, 0xaa),
+        deo2(BUFFER + 4, 0xa4),
+        deo2(DATA, 0xa8), // a file named "hello"
+        deo2(2, 0xaa),
+        deo2(BUFFER + 5, 0xa4),
+    ]);
+
+    let cpu = run(&rom, storage);
+
+    assert_eq!(success(&cpu), 2);
+    assert_eq!(ram(&cpu, BUFFER, 7), b"001f?!!");
+}
+
+#[test]
+fn test_file_delete() {
+    let storage = Rc::new(RefCell::new(MemoryStorage::new()));
+    storage.borrow_mut().insert("a.txt", b"hello");
+    let rom = file_rom(&[deo2(NAME, 0xa8), deo(0x01, 0xa6)]);
+
+    let cpu = run(&rom, storage.clone());
+
+    assert_eq!(success(&cpu), 1);
+    assert_eq!(storage.borrow().get("a.txt"), None);
+}
+
+#[test]
+fn test_file_without_storage() {
+    let rom = file_rom(&[deo2(NAME, 0xa8), deo2(5, 0xaa), deo2(DATA, 0xae)]);
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new();
+
+    vm.on_reset(&mut cpu).unwrap();
+
+    assert_eq!(success(&cpu), 0);
+}
+
+#[test]
+fn test_directory_storage() {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("file_test");
+    fs::create_dir_all(&dir).unwrap();
+    let rom = file_rom(&[deo2(NAME, 0xa8), deo2(5, 0xaa), deo2(DATA, 0xae)]);
+
+    let cpu = run(&rom, DirectoryStorage::new(&dir));
+
+    assert_eq!(success(&cpu), 5);
+    assert_eq!(fs::read(dir.join("a.txt")).unwrap(), b"hello");
+}
+
+#[test]
+fn test_directory_storage_is_sandboxed() {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("file_test");
+    let mut storage = DirectoryStorage::new(&dir);
+
+    for name in ["../a.txt", "/etc/passwd", "saves/../../a.txt", ""] {
+        let err = storage.write(name, b"hello", false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied, "{}", name);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_directory_storage_rejects_symlinks_outside() {
+    let tmp = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("file_test_symlinks");
+    let _ = fs::remove_dir_all(&tmp);
+    let dir = tmp.join("files");
+    let outside = tmp.join("outside");
+    fs::create_dir_all(&dir).unwrap();
+    fs::create_dir_all(&outside).unwrap();
+    fs::write(outside.join("secret.txt"), b"secret").unwrap();
+    std::os::unix::fs::symlink(&outside, dir.join("out")).unwrap();
+    std::os::unix::fs::symlink(outside.join("secret.txt"), dir.join("secret.txt")).unwrap();
+    std::os::unix::fs::symlink(outside.join("new.txt"), dir.join("new.txt")).unwrap();
+    let mut storage = DirectoryStorage::new(&dir);
+
+    for name in ["out/secret.txt", "out/new.txt", "secret.txt"] {
+        let err = storage.read(name).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied, "{}", name);
+        let err = storage.write(name, b"hello", false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied, "{}", name);
+    }
+    // a dangling symlink can't be written through either
+    assert!(storage.write("new.txt", b"hello", false).is_err());
+
+    assert_eq!(fs::read(outside.join("secret.txt")).unwrap(), b"secret");
+    assert!(!outside.join("new.txt").exists());
+}
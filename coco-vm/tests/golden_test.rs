@@ -0,0 +1,160 @@
+//! Runs the ROMs in `coco-ui/roms` and compares their screen against the
+//! reference images in `tests/golden`.
+//!
+//! To update the reference images after an intended change, run the tests
+//! with `COCO_UPDATE_GOLDEN=1`.
+
+use std::env;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use coco_core::Cpu;
+use coco_vm::{Vm, SCREEN_HEIGHT, SCREEN_WIDTH, THEME};
+
+const WIDTH: u32 = SCREEN_WIDTH as u32;
+const HEIGHT: u32 = SCREEN_HEIGHT as u32;
+
+/// Color of the pixels that differ in a diff image
+const DIFF_COLOR: [u8; 4] = [0xff, 0x00, 0xff, 0xff];
+
+fn roms_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../coco-ui/roms")
+}
+
+fn golden_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+/// Runs the reset vector and `frames` video frames, and returns the screen
+/// as RGBA
+fn run_rom(name: &str, frames: usize) -> Vec<u8> {
+    let rom = std::fs::read(roms_dir().join(format!("{}.rom", name))).unwrap();
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new();
+
+    vm.on_reset(&mut cpu).unwrap();
+    for _ in 0..frames {
+        vm.on_video(&mut cpu).unwrap();
+    }
+
+    vm.pixels()
+        .iter()
+        .flat_map(|&pixel| {
+            let (r, g, b) = THEME[pixel as usize];
+            [r, g, b, 0xff]
+        })
+        .collect()
+}
+
+fn read_png(path: &Path) -> Vec<u8> {
+    let decoder = png::Decoder::new(File::open(path).unwrap());
+    let mut reader = decoder.read_info().unwrap();
+    let mut data = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut data).unwrap();
+    data
+}
+
+fn write_png(path: &Path, data: &[u8]) {
+    let file = BufWriter::new(File::create(path).unwrap());
+    let mut encoder = png::Encoder::new(file, WIDTH, HEIGHT);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .unwrap()
+        .write_image_data(data)
+        .unwrap();
+}
+
+/// Returns an image where the pixels that differ are highlighted, and the
+/// rest are dimmed, along with the number of different pixels
+fn diff_image(expected: &[u8], actual: &[u8]) -> (Vec<u8>, usize) {
+    let mut diff = Vec::with_capacity(actual.len());
+    let mut count = 0;
+
+    for (a, b) in expected.chunks(4).zip(actual.chunks(4)) {
+        if a == b {
+            diff.extend(a[..3].iter().map(|x| x / 4));
+            diff.push(0xff);
+        } else {
+            diff.extend_from_slice(&DIFF_COLOR);
+            count += 1;
+        }
+    }
+
+    (diff, count)
+}
+
+/// Number of video frames to run before taking the screenshot, for ROMs that
+/// need more than one to draw their screen
+const FRAMES: &[(&str, usize)] = &[("pacman", 40)];
+
+/// Returns the names of the ROMs in `coco-ui/roms`, sorted
+fn rom_names() -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(roms_dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "rom"))
+        .map(|path| path.file_stem().unwrap().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+/// Compares the screen of a ROM against its reference image, and returns
+/// why they don't match, if they don't
+fn check_golden(name: &str) -> Result<(), String> {
+    let frames = FRAMES
+        .iter()
+        .find(|(rom, _)| *rom == name)
+        .map_or(1, |&(_, frames)| frames);
+    let actual = run_rom(name, frames);
+    let golden = golden_dir().join(format!("{}.png", name));
+
+    if env::var_os("COCO_UPDATE_GOLDEN").is_some() {
+        write_png(&golden, &actual);
+        return Ok(());
+    }
+
+    if !golden.exists() {
+        return Err(format!(
+            "{} has no reference image at {}",
+            name,
+            golden.display()
+        ));
+    }
+    let expected = read_png(&golden);
+    if expected == actual {
+        return Ok(());
+    }
+
+    let out_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let actual_path = out_dir.join(format!("{}-actual.png", name));
+    let diff_path = out_dir.join(format!("{}-diff.png", name));
+    let (diff, count) = diff_image(&expected, &actual);
+    write_png(&actual_path, &actual);
+    write_png(&diff_path, &diff);
+
+    Err(format!(
+        "{} differs from {} in {} pixels\n  actual: {}\n  diff: {}",
+        name,
+        golden.display(),
+        count,
+        actual_path.display(),
+        diff_path.display()
+    ))
+}
+
+#[test]
+fn test_roms_match_golden() {
+    let names = rom_names();
+    assert!(!names.is_empty(), "no ROMs in {}", roms_dir().display());
+
+    let failures: Vec<String> = names
+        .iter()
+        .filter_map(|name| check_golden(name).err())
+        .collect();
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
@@ -0,0 +1,59 @@
+use coco_vm::{tal_header, DeviceDescription, PortDescription, DEVICES};
+
+#[test]
+fn test_device_description_as_tal() {
+    const DEVICE: DeviceDescription = DeviceDescription {
+        name: "Screen",
+        base: 0x10,
+        ports: &[
+            PortDescription::short("vector", 0x00),
+            PortDescription::byte("x", 0x02),
+            PortDescription::short("address", 0x08),
+        ],
+    };
+
+    assert_eq!(
+        format!("{}", DEVICE),
+        "|10 @Screen &vector $2 &x $1 $5 &address $2"
+    );
+}
+
+#[test]
+fn test_devices_do_not_overlap() {
+    for pair in DEVICES.windows(2) {
+        assert!(pair[0].base < pair[1].base);
+    }
+    for device in DEVICES {
+        let mut offset = 0;
+        for port in device.ports {
+            assert!(port.offset >= offset, "{}/{}", device.name, port.name);
+            offset = port.offset + port.size;
+        }
+        assert!(offset <= 0x10, "{}", device.name);
+    }
+}
+
+#[test]
+fn test_tal_header() {
+    let header = tal_header();
+
+    assert!(header.contains("|00 @System &vector $2 &debug $1 $1 &random $2 $9 &state $1\n"));
+    assert!(header.contains(
+        "|10 @Screen &vector $2 &x $1 &y $1 &pixel $1 &width $1 &height $1 $1 &address $2 &sprite $1\n"
+    ));
+    assert!(header.contains("|20 @Console &vector $2 &read $1 $4 &type $1 &write $1 &error $1\n"));
+    assert!(header.contains(
+        "|30 @Audio0 &vector $2 &position $2 &output $1 &wave $1 $2 &adsr $2 &length $2 &address $2 &volume $1 &pitch $1\n"
+    ));
+    assert!(header.contains("|60 @Audio3 "));
+    assert!(header.contains(
+        "|a0 @File &vector $2 &success $2 &stat $2 &delete $1 &append $1 &name $2 &length $2 &read $2 &write $2\n"
+    ));
+    assert!(header.contains(
+        "|c0 @DateTime &year $2 &month $1 &day $1 &hour $1 &minute $1 &second $1 &dotw $1 &doty $2 &isdst $1\n"
+    ));
+    assert!(header.contains("|80 @Controller &vector $2 &button $1 &key $1\n"));
+    assert!(
+        header.contains("|90 @Mouse &vector $2 &x $2 &y $2 &state $1 $3 &scrollx $2 &scrolly $2\n")
+    );
+}
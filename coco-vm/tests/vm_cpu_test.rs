@@ -1,8 +1,8 @@
 use coco_core::opcodes::*;
-use coco_core::Cpu;
+use coco_core::{Cpu, Fault};
 use coco_vm::SCREEN_HEIGHT;
 use coco_vm::VIDEO_BUFFER_LEN;
-use coco_vm::{Vm, SCREEN_WIDTH};
+use coco_vm::{Button, MouseButton, Vm, SCREEN_WIDTH};
 
 #[test]
 fn test_deo_system_debug() {
@@ -10,7 +10,7 @@ fn test_deo_system_debug() {
     let mut cpu = Cpu::new(&rom);
     let mut vm = Vm::new();
 
-    let output = vm.on_reset(&mut cpu);
+    let output = vm.on_reset(&mut cpu).unwrap();
 
     let expected_sys_output = "WRK: [ff]\nRET: []".to_string();
     assert_eq!(output.sys_stdout, expected_sys_output);
@@ -24,10 +24,10 @@ fn test_deo_video_pixel_put() {
     let mut cpu = Cpu::new(&rom);
     let mut vm = Vm::new();
 
-    let _ = vm.on_reset(&mut cpu);
+    vm.on_reset(&mut cpu).unwrap();
     let buffer = vm.pixels();
 
-    assert_eq!(buffer[0x01 * SCREEN_WIDTH as usize + 0x01], 0x08);
+    assert_eq!(buffer[SCREEN_WIDTH as usize + 0x01], 0x08);
 }
 
 #[test]
@@ -53,7 +53,7 @@ fn test_deo_video_pixel_fill() {
     let mut cpu = Cpu::new(&rom);
     let mut vm = Vm::new();
 
-    let _ = vm.on_reset(&mut cpu);
+    vm.on_reset(&mut cpu).unwrap();
     let buffer = vm.pixels();
 
     assert_eq!(
@@ -94,7 +94,7 @@ fn test_deo_video_pixel_fill_with_flip() {
     let mut cpu = Cpu::new(&rom);
     let mut vm = Vm::new();
 
-    let _ = vm.on_reset(&mut cpu);
+    vm.on_reset(&mut cpu).unwrap();
     let buffer = vm.pixels();
 
     let expected_slice = [
@@ -124,8 +124,373 @@ fn test_deo_sprite() {
     let mut cpu = Cpu::new(&rom);
     let mut vm = Vm::new();
 
-    let _ = vm.on_reset(&mut cpu);
+    vm.on_reset(&mut cpu).unwrap();
     let buffer = vm.pixels();
 
     assert_eq!(buffer[0..8], [0x01; 8]);
 }
+
+#[test]
+fn test_deo_unknown_device_faults() {
+    let rom = [PUSH, 0xab, PUSH, 0xf0, DEO, BRK];
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new();
+
+    let trap = vm.on_reset(&mut cpu).unwrap_err();
+
+    assert_eq!(trap.fault, Fault::UnknownDevice(0xf0));
+    assert_eq!(trap.pc, 0x104);
+    assert_eq!(trap.opcode, DEO);
+}
+
+#[test]
+fn test_dei_unknown_device_faults() {
+    let rom = [PUSH, 0xf0, DEI, BRK];
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new();
+
+    let trap = vm.on_reset(&mut cpu).unwrap_err();
+
+    assert_eq!(trap.fault, Fault::UnknownDevice(0xf0));
+    assert_eq!(trap.opcode, DEI);
+}
+
+#[test]
+fn test_dei_video_screen_size() {
+    let rom = [PUSH, 0x15, DEI, PUSH, 0x16, DEI, PUSH, 0x15, DEI2, BRK];
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new();
+
+    vm.on_reset(&mut cpu).unwrap();
+
+    assert_eq!(
+        cpu.working_stack(),
+        [SCREEN_WIDTH, SCREEN_HEIGHT, SCREEN_WIDTH, SCREEN_HEIGHT]
+    );
+}
+
+#[test]
+fn test_dei_system_random() {
+    let rom = [PUSH, 0x04, DEI2, PUSH, 0x04, DEI2, PUSH, 0x04, DEI2, BRK];
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new();
+
+    vm.on_reset(&mut cpu).unwrap();
+
+    let stack = cpu.working_stack();
+    let numbers: Vec<u16> = stack
+        .chunks_exact(2)
+        .map(|short| u16::from_be_bytes([short[0], short[1]]))
+        .collect();
+    assert_eq!(numbers.len(), 3);
+    assert!(numbers.iter().all(|&n| n != 0x0000));
+    assert_ne!(numbers[0], numbers[1]);
+    assert_ne!(numbers[1], numbers[2]);
+}
+
+#[test]
+fn test_dei_system_random_with_seed() {
+    let rom = [PUSH, 0x04, DEI2, PUSH, 0x04, DEI2, BRK];
+    let mut numbers = vec![];
+    for seed in [0x1234, 0x1234, 0x4321, 0x0000] {
+        let mut cpu = Cpu::new(&rom);
+        let mut vm = Vm::new().with_seed(seed);
+        vm.on_reset(&mut cpu).unwrap();
+        numbers.push(cpu.working_stack().to_vec());
+    }
+
+    assert_eq!(numbers[0], numbers[1]);
+    assert_ne!(numbers[0], numbers[2]);
+    assert_ne!(numbers[3], [0x00; 4]);
+}
+
+#[test]
+fn test_dei_mouse_position() {
+    let rom = [BRK, PUSH, 0x92, DEI2, PUSH, 0x94, DEI2, BRK];
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new();
+
+    vm.on_reset(&mut cpu).unwrap();
+    vm.on_mouse_move(&mut cpu, 0x0012, 0x0034).unwrap();
+    vm.on_vector(&mut cpu, 0x0101).unwrap();
+
+    assert_eq!(cpu.working_stack(), [0x00, 0x12, 0x00, 0x34]);
+}
+
+#[test]
+fn test_deo_system_state_halts() {
+    let rom = [
+        PUSH, 0x00, PUSH, 0x0f, DEO, BRK, PUSH, 0x01, PUSH, 0x0f, DEO, BRK,
+    ];
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new();
+
+    let output = vm.on_reset(&mut cpu).unwrap();
+    assert!(!output.shall_halt);
+    let output = vm.on_vector(&mut cpu, 0x0106).unwrap();
+
+    assert!(output.shall_halt);
+    assert_eq!(output.exit_code, 0x01);
+}
+
+#[test]
+fn test_deo_system_state_keeps_exit_code() {
+    let rom = [PUSH, 0x85, PUSH, 0x0f, DEO, BRK];
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new();
+
+    let output = vm.on_reset(&mut cpu).unwrap();
+
+    assert!(output.shall_halt);
+    assert_eq!(output.exit_code, 0x05);
+}
+
+#[test]
+fn test_video_vector_is_skipped_when_unset() {
+    // a screen vector at 0x0000 would run the zero page as code
+    let rom = [BRK];
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new();
+
+    vm.on_reset(&mut cpu).unwrap();
+    let output = vm.on_video(&mut cpu).unwrap();
+
+    assert!(!output.is_paused);
+    assert_eq!(cpu.pc(), 0x0101);
+}
+
+#[test]
+fn test_system_vector_catches_faults() {
+    let rom = [
+        PUSH2, 0x01, 0x0c, PUSH, 0x00, DEO2, // set system vector to 0x010c
+        PUSH, 0x01, ADD, // stack underflow
+        BRK, BRK, BRK, // padding
+        PUSH, 0x01, PUSH, 0x02, DEO, // debug stacks
+        BRK,
+    ];
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new();
+
+    let output = vm.on_reset(&mut cpu).unwrap();
+
+    let expected_sys_output = "WRK: [01 08 18 01]\nRET: []".to_string();
+    assert_eq!(output.sys_stdout, expected_sys_output);
+}
+
+#[test]
+fn test_system_vector_recovers_from_return_stack_overflow() {
+    let rom = [
+        PUSH2,
+        0x01,
+        0x09,
+        PUSH,
+        0x00,
+        DEO2, // set system vector to 0x0109
+        JSI,
+        0xff,
+        0xfd, // calls itself until the return stack overflows
+        JSI,
+        0x00,
+        0x01, // handler: call a subroutine at 0x010d
+        BRK,  //
+        PUSH,
+        0x01,
+        PUSH,
+        0x02,
+        DEO,             // debug stacks
+        JMP2 | FLAG_RET, // return
+    ];
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new();
+
+    let output = vm.on_reset(&mut cpu).unwrap();
+
+    let expected_sys_output = "WRK: [01 06 60 02]\nRET: [01 0c]".to_string();
+    assert_eq!(output.sys_stdout, expected_sys_output);
+}
+
+#[test]
+fn test_system_vector_faults_are_not_caught() {
+    let rom = [
+        PUSH2, 0x01, 0x09, PUSH, 0x00, DEO2, // set system vector to 0x0109
+        POP,  // stack underflow
+        BRK, BRK, // padding
+        POP2, POP2, POP2, // stack underflow in the handler
+        BRK,
+    ];
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new();
+
+    let trap = vm.on_reset(&mut cpu).unwrap_err();
+
+    assert_eq!(trap.fault, Fault::StackUnderflow);
+    assert_eq!(trap.pc, 0x10b);
+    assert_eq!(trap.opcode, POP2);
+}
+
+#[test]
+fn test_runaway_vector_is_paused() {
+    let rom = [PUSH, 0x01, JMI, 0xff, 0xfd]; // infinite loop
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new();
+
+    let output = vm.on_reset(&mut cpu).unwrap();
+
+    assert!(output.is_paused);
+    assert_eq!(cpu.pc(), 0x102);
+}
+
+/// A ROM whose controller vector outputs the given port of the controller
+fn controller_rom(port: u8) -> [u8; 14] {
+    [
+        PUSH2, 0x01, 0x07, PUSH, 0x80, DEO2, // set controller vector to 0x0107
+        BRK,  // end of reset vector
+        PUSH, port, DEI, // read the port
+        PUSH, 0x01, PUSH, 0x02, // output debug info
+    ]
+}
+
+#[test]
+fn test_controller_vector_gets_buttons() {
+    let mut rom = controller_rom(0x82).to_vec();
+    rom.extend_from_slice(&[DEO, POP, BRK]);
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new();
+    vm.on_reset(&mut cpu).unwrap();
+
+    let output = vm
+        .on_buttons(&mut cpu, Button::A as u8 | Button::Up as u8)
+        .unwrap();
+
+    assert_eq!(output.sys_stdout, "WRK: [11]\nRET: []".to_string());
+}
+
+#[test]
+fn test_controller_vector_fires_on_change() {
+    let mut rom = controller_rom(0x82).to_vec();
+    rom.extend_from_slice(&[DEO, POP, BRK]);
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new();
+    vm.on_reset(&mut cpu).unwrap();
+
+    vm.on_buttons(&mut cpu, Button::Start as u8).unwrap();
+    let unchanged = vm.on_buttons(&mut cpu, Button::Start as u8).unwrap();
+    let released = vm.on_buttons(&mut cpu, 0x00).unwrap();
+
+    assert_eq!(unchanged.sys_stdout, "".to_string());
+    assert_eq!(released.sys_stdout, "WRK: [00]\nRET: []".to_string());
+}
+
+#[test]
+fn test_controller_vector_gets_keys() {
+    let mut rom = controller_rom(0x83).to_vec();
+    rom.extend_from_slice(&[DEO, POP, BRK]);
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new();
+    vm.on_reset(&mut cpu).unwrap();
+
+    let output = vm.on_key(&mut cpu, b'a').unwrap();
+
+    assert_eq!(output.sys_stdout, "WRK: [61]\nRET: []".to_string());
+    assert_eq!(cpu.device_peek_byte(0x83), 0x00);
+}
+
+#[test]
+fn test_controller_without_vector() {
+    let rom = [BRK];
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new();
+    vm.on_reset(&mut cpu).unwrap();
+
+    let output = vm.on_buttons(&mut cpu, Button::B as u8).unwrap();
+
+    assert_eq!(output.sys_stdout, "".to_string());
+    assert_eq!(cpu.device_peek_byte(0x82), Button::B as u8);
+}
+
+/// A ROM whose mouse vector outputs the given short port of the mouse
+fn mouse_rom(port: u8) -> [u8; 17] {
+    [
+        PUSH2, 0x01, 0x07, PUSH, 0x90, DEO2, // set mouse vector to 0x0107
+        BRK,  // end of reset vector
+        PUSH, port, DEI2, // read the port
+        PUSH, 0x01, PUSH, 0x02, DEO, // output debug info
+        POP2, BRK,
+    ]
+}
+
+#[test]
+fn test_mouse_vector_gets_position() {
+    let rom = mouse_rom(0x92);
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new();
+    vm.on_reset(&mut cpu).unwrap();
+
+    let output = vm.on_mouse_move(&mut cpu, 0x00bf, 0x008f).unwrap();
+    let unchanged = vm.on_mouse_move(&mut cpu, 0x00bf, 0x008f).unwrap();
+
+    assert_eq!(output.sys_stdout, "WRK: [00 bf]\nRET: []".to_string());
+    assert_eq!(unchanged.sys_stdout, "".to_string());
+    assert_eq!(cpu.device_peek_byte(0x95), 0x8f);
+}
+
+#[test]
+fn test_mouse_vector_gets_buttons() {
+    let rom = mouse_rom(0x96);
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new();
+    vm.on_reset(&mut cpu).unwrap();
+
+    let buttons = MouseButton::Left as u8 | MouseButton::Right as u8;
+    let output = vm.on_mouse_buttons(&mut cpu, buttons).unwrap();
+    let unchanged = vm.on_mouse_buttons(&mut cpu, buttons).unwrap();
+
+    assert_eq!(output.sys_stdout, "WRK: [05 00]\nRET: []".to_string());
+    assert_eq!(unchanged.sys_stdout, "".to_string());
+}
+
+#[test]
+fn test_mouse_vector_gets_scroll() {
+    let rom = mouse_rom(0x9c);
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new();
+    vm.on_reset(&mut cpu).unwrap();
+
+    let output = vm.on_mouse_scroll(&mut cpu, 0, -1).unwrap();
+
+    assert_eq!(output.sys_stdout, "WRK: [ff ff]\nRET: []".to_string());
+    assert_eq!(cpu.device_peek_byte(0x9c), 0x00);
+    assert_eq!(cpu.device_peek_byte(0x9d), 0x00);
+}
+
+#[test]
+fn test_deo_console_write() {
+    let rom = [
+        PUSH, b'h', PUSH, 0x28, DEO, PUSH, b'i', PUSH, 0x28, DEO, PUSH, b'!', PUSH, 0x29, DEO, BRK,
+    ];
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new();
+
+    let output = vm.on_reset(&mut cpu).unwrap();
+
+    assert_eq!(output.console_stdout, b"hi".to_vec());
+    assert_eq!(output.console_stderr, b"!".to_vec());
+}
+
+#[test]
+fn test_console_vector_gets_input() {
+    let rom = [
+        PUSH2, 0x01, 0x07, PUSH, 0x20, DEO2, // set console vector to 0x0107
+        BRK,  // end of reset vector
+        PUSH, 0x22, DEI, PUSH, 0x28, DEO, // echo the read byte
+        BRK,
+    ];
+    let mut cpu = Cpu::new(&rom);
+    let mut vm = Vm::new();
+    vm.on_reset(&mut cpu).unwrap();
+
+    let output = vm.on_console(&mut cpu, b'x').unwrap();
+
+    assert_eq!(output.console_stdout, b"x".to_vec());
+    assert_eq!(cpu.device_peek_byte(0x27), 0x01);
+}